@@ -0,0 +1,113 @@
+//! With the `embed-static` feature, bundles `static/` into the binary: each file with its content
+//! type, an ETag, and gzip/brotli variants compressed ahead of time
+
+fn main() {
+    #[cfg(feature = "embed-static")]
+    embed_static::generate();
+}
+
+#[cfg(feature = "embed-static")]
+mod embed_static {
+    use std::{
+        env,
+        fmt::Write as _,
+        fs,
+        io::Write as _,
+        path::{Path, PathBuf},
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+    use sha2::{Digest, Sha256};
+
+    const STATIC_DIR: &str = "static";
+
+    fn content_type(path: &Path) -> &'static str {
+        match path.extension().and_then(|e| e.to_str()).unwrap_or_default() {
+            "html" => "text/html; charset=utf-8",
+            "js" => "text/javascript; charset=utf-8",
+            "css" => "text/css; charset=utf-8",
+            "json" | "map" => "application/json",
+            "webmanifest" => "application/manifest+json",
+            "svg" => "image/svg+xml",
+            "png" => "image/png",
+            "ico" => "image/x-icon",
+            "woff2" => "font/woff2",
+            "txt" => "text/plain; charset=utf-8",
+            _ => "application/octet-stream",
+        }
+    }
+
+    /// every file under `dir`, sorted
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+        let mut entries: Vec<_> = fs::read_dir(dir)
+            .unwrap_or_else(|err| panic!("could not read {}: {err}", dir.display()))
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                walk(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        let params = brotli::enc::BrotliEncoderParams { quality: 11, ..Default::default() };
+        brotli::BrotliCompress(&mut &data[..], &mut output, &params).unwrap();
+        output
+    }
+
+    /// write a compressed variant to `OUT_DIR`, if it's actually smaller
+    fn variant(out_dir: &Path, name: &str, original: &[u8], compressed: Vec<u8>) -> String {
+        // not worth it for images that are already compressed
+        if compressed.len() >= original.len() * 9 / 10 {
+            return "None".into();
+        }
+        let path = out_dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, compressed).unwrap();
+        format!("Some(include_bytes!({:?}))", path)
+    }
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed={STATIC_DIR}");
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join(STATIC_DIR);
+        let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+        if !root.join("main.js").exists() {
+            println!("cargo:warning=static/main.js is missing, so the embedded frontend won't work. Run `node build.js` first");
+        }
+
+        let mut files = vec![];
+        walk(&root, &mut files);
+
+        let mut code = String::from("&[\n");
+        for path in files {
+            let data = fs::read(&path).unwrap();
+            let name = path.strip_prefix(&root).unwrap().to_str().expect("static file names are UTF-8");
+            let hash = Sha256::digest(&data);
+            let etag: String = hash[..16].iter().map(|byte| format!("{byte:02x}")).collect();
+
+            let gzip = variant(&out_dir, &format!("{STATIC_DIR}/{name}.gz"), &data, gzip(&data));
+            let br = variant(&out_dir, &format!("{STATIC_DIR}/{name}.br"), &data, brotli(&data));
+            writeln!(
+                code,
+                "    EmbeddedFile {{ path: {name:?}, content_type: {:?}, etag: \"\\\"{etag}\\\"\", data: include_bytes!({path:?}), gzip: {gzip}, br: {br} }},",
+                content_type(&path),
+            ).unwrap();
+        }
+        code.push(']');
+
+        fs::write(out_dir.join("embedded_static.rs"), code).unwrap();
+    }
+}
@@ -10,6 +10,7 @@ import { createRef, Ref, ref } from "lit/directives/ref.js";
 import CreateGroupModal from "./create-group-modal";
 
 const onlineIndicator = html`<span class="flex w-2 h-2 ms-auto bg-emerald-500 rounded-full" title="Online"></span>`;
+const botLabel = html`<span class="ms-2 px-1 text-xs rounded bg-gray-700 text-gray-300">BOT</span>`;
 
 @customElement("side-bar")
 export default class Sidebar extends StyledElement {
@@ -62,6 +63,7 @@ export default class Sidebar extends StyledElement {
                  class="px-3 py-1 flex text-left w-full outline outline-1 outline-orange-600 bg-orange-950 last:border-b-0 items-center first:rounded-t last:rounded-b"
             >
             ${user.name}
+            ${user.bot ? botLabel : nothing}
         
             ${user.online ? onlineIndicator : nothing}
           </p>
@@ -73,6 +75,7 @@ export default class Sidebar extends StyledElement {
                  type="button" @click=${() => this.userClicked(user.id)}
             >
             ${user.name}
+            ${user.bot ? botLabel : nothing}
         
             ${user.online ? onlineIndicator : nothing}
           </button>
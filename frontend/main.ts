@@ -26,6 +26,11 @@ export class StcApp extends StyledElement {
   private users: ServerUser[] = [];
   @state()
   private groups: ServerGroup[] = [];
+  // blocked users are hidden from the user list
+  @state()
+  private blockedUsers: ServerUser[] = [];
+  @state()
+  private muted: MessageRecipient[] = [];
   @state()
   private currentRecipient: MessageRecipient | null = null;
   @state()
@@ -139,6 +144,10 @@ export class StcApp extends StyledElement {
     return ("User" in r && "User" in cr && r.User === cr.User) || ("Group" in r && "Group" in cr && r.Group === cr.Group) || ("User" in cr && cr.User === msg.sender);
   }
 
+  private isBlocked(id: number) {
+    return this.blockedUsers.some(el => el.id === id);
+  }
+
   private onMessage(msg: ServerMessage) {
     switch (msg.type) {
       case "Error":
@@ -151,16 +160,89 @@ export class StcApp extends StyledElement {
         this.userId = msg.user_id;
         this.users = msg.users;
         this.groups = msg.groups;
+        this.blockedUsers = msg.blocked;
+        this.muted = msg.muted;
+        if (msg.vapid_public_key) this.subscribePush(msg.vapid_public_key);
+        break;
+      case "UserBlocked": {
+        if (msg.blocked) {
+          const user = this.users.find(el => el.id === msg.id);
+          this.users = this.users.filter(el => el.id !== msg.id);
+          if (user) this.blockedUsers = [...this.blockedUsers, user];
+        } else {
+          const user = this.blockedUsers.find(el => el.id === msg.id);
+          this.blockedUsers = this.blockedUsers.filter(el => el.id !== msg.id);
+          if (user) this.users = [...this.users, user];
+        }
+        break;
+      }
+      case "ConversationMuted": {
+        const { conversation } = msg;
+        const others = this.muted.filter(el =>
+          !(("User" in el && "User" in conversation && el.User === conversation.User) ||
+            ("Group" in el && "Group" in conversation && el.Group === conversation.Group)));
+        this.muted = msg.muted ? [...others, conversation] : others;
+        break;
+      }
+      case "Disconnected":
+        showToast(msg.reason, "warning");
+        break;
+      case "CommandResponse":
+        showToast(msg.text, "success", 10000);
+        break;
+      case "Reminder":
+        showToast(`Reminder: ${msg.message.message}`, "warning", 15000);
+        break;
+      case "ReminderCreated":
+        showToast(`Reminder set for ${new Date(msg.reminder.time * 1000).toLocaleString()}`);
         break;
       case "UserAdded":
+        // blocked users stay out of the user list
+        if (this.isBlocked(msg.user.id)) break;
         this.users = [...this.users, msg.user];
         break;
+      case "UserRenamed":
+      case "UserDeleted": {
+        const idx = this.users.findIndex(el => el.id === msg.id);
+        if (idx >= 0) {
+          const item = this.users[idx];
+          if (msg.type === "UserRenamed") {
+            item.name = msg.name;
+          } else {
+            // deleted users stay around so their old messages have a sender
+            item.name = "[deleted]";
+            item.online = false;
+          }
+          this.users = this.users.with(idx, item);
+        } else if (msg.type === "UserRenamed" && msg.id === this.userId) {
+          this.username = msg.name;
+          localStorage.setItem("username", this.username);
+        }
+        break;
+      }
+      case "UserUpdated": {
+        // keep blocked users up to date, but don't show them again
+        if (this.isBlocked(msg.user.id)) {
+          this.blockedUsers = this.blockedUsers.map(el => el.id === msg.user.id ? msg.user : el);
+          break;
+        }
+        const idx = this.users.findIndex(el => el.id === msg.user.id);
+        if (idx >= 0) {
+          this.users = this.users.with(idx, msg.user);
+        }
+        break;
+      }
       case "UserOnline":
       case "UserOffline": {
+        const online = msg.type === "UserOnline";
+        if (this.isBlocked(msg.id)) {
+          this.blockedUsers = this.blockedUsers.map(el => el.id === msg.id ? { ...el, online } : el);
+          break;
+        }
         const idx = this.users.findIndex(el => el.id === msg.id);
         if (idx >= 0) {
           const item = this.users[idx];
-          item.online = msg.type === "UserOnline";
+          item.online = online;
           this.users = this.users.with(idx, item);
         }
         break;
@@ -226,6 +308,24 @@ export class StcApp extends StyledElement {
     }
   }
   
+  /// register for push notifications so messages arrive while the app is closed
+  private async subscribePush(vapidPublicKey: string) {
+    if (!("serviceWorker" in navigator) || !("PushManager" in window)) return;
+    if (await Notification.requestPermission() !== "granted") return;
+
+    const registration = await navigator.serviceWorker.ready;
+    const subscription = await registration.pushManager.subscribe({
+      userVisibleOnly: true,
+      applicationServerKey: decodeBase64Url(vapidPublicKey)
+    });
+    this.socket.send({
+      type: "RegisterPush",
+      endpoint: subscription.endpoint,
+      p256dh: encodeBase64Url(subscription.getKey("p256dh")),
+      auth: encodeBase64Url(subscription.getKey("auth"))
+    });
+  }
+
   render() {
     const rec = this.currentRecipient;
     // title on the message list
@@ -271,6 +371,17 @@ export class StcApp extends StyledElement {
   }
 }
 
+function decodeBase64Url(data: string) {
+  const binary = atob(data.replace(/-/g, "+").replace(/_/g, "/"));
+  return Uint8Array.from(binary, c => c.charCodeAt(0));
+}
+
+function encodeBase64Url(data: ArrayBuffer | null) {
+  if (!data) return "";
+  return btoa(String.fromCharCode(...new Uint8Array(data)))
+    .replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
 if ("serviceWorker" in navigator)
   navigator.serviceWorker.register("/service-worker.js");
 
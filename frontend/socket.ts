@@ -1,5 +1,7 @@
 import { encode, decode } from "@msgpack/msgpack";
 
+export type GroupRole = "Member" | "Admin" | "Owner";
+
 export type MessageRecipient = {
   User: number
 } | {
@@ -10,13 +12,37 @@ export type MessageRecipient = {
 export type ClientMessage = {
   type: "RequestUsername",
   username: string
+} | {
+  type: "UpdateProfile",
+  // null leaves a field unchanged, empty clears it
+  display_name: string | null,
+  status: string | null,
+  avatar: Uint8Array | null
 } | {
   type: "GetMessages",
   recipient: MessageRecipient
 } | {
+  // messages starting with `/` run a command. start with `//` to send a leading slash
   type: "SendMessage",
   message: string,
   recipient: MessageRecipient
+} | {
+  type: "ListCommands"
+} | {
+  type: "CreateReminder",
+  message: number,
+  // unix timestamp (seconds)
+  time: number
+} | {
+  type: "ListReminders"
+} | {
+  type: "CancelReminder",
+  id: number
+} | {
+  // bots only
+  type: "RegisterCommand",
+  name: string,
+  description: string
 } | {
   type: "EditMessage",
   id: number,
@@ -28,30 +54,204 @@ export type ClientMessage = {
 } | {
   type: "DeleteMessage",
   id: number
+} | {
+  type: "ForwardMessage",
+  id: number,
+  recipient: MessageRecipient
 } | {
   type: "CreateGroup",
   name: string,
-  members: number[]
+  members: number[],
+  public?: boolean
 } | {
   type: "EditGroup",
   id: number,
   new_name: string,
   new_members: number[]
+} | {
+  type: "SetGroupRole",
+  id: number,
+  user: number,
+  role: GroupRole
+} | {
+  type: "AddMembers",
+  id: number,
+  members: number[]
+} | {
+  type: "RemoveMembers",
+  id: number,
+  members: number[]
+} | {
+  type: "LeaveGroup",
+  id: number
 } | {
   type: "DeleteGroup",
   id: number
+} | {
+  type: "SetGroupVisibility",
+  id: number,
+  public: boolean
+} | {
+  type: "ListPublicChannels"
+} | {
+  type: "JoinChannel",
+  id: number
+} | {
+  type: "CreateInvite",
+  group: number,
+  // seconds until the invite expires
+  expires_in: number | null,
+  max_uses: number | null
+} | {
+  type: "ListInvites",
+  group: number
+} | {
+  type: "RevokeInvite",
+  token: string
+} | {
+  type: "RedeemInvite",
+  token: string
+} | {
+  type: "RenameUser",
+  id: number,
+  new_name: string
+} | {
+  type: "SetUserDeactivated",
+  id: number,
+  deactivated: boolean
+} | {
+  type: "DeleteUser",
+  id: number,
+  erase_messages: boolean
+} | {
+  type: "SetUserAdmin",
+  id: number,
+  admin: boolean
+} | {
+  type: "ListAllGroups"
+} | {
+  type: "GetStats"
+} | {
+  type: "DisconnectUser",
+  id: number
+} | {
+  type: "RegisterPush",
+  endpoint: string,
+  p256dh: string,
+  auth: string
+} | {
+  type: "UnregisterPush",
+  endpoint: string
+} | {
+  type: "CreateWebhook",
+  url: string,
+  trigger: WebhookTrigger
+} | {
+  type: "ListWebhooks"
+} | {
+  type: "DeleteWebhook",
+  id: number
+} | {
+  type: "CreateIncomingWebhook",
+  recipient: MessageRecipient,
+  bot_name: string
+} | {
+  type: "ListIncomingWebhooks"
+} | {
+  type: "DeleteIncomingWebhook",
+  bot: number
+} | {
+  type: "CreateBot",
+  name: string
+} | {
+  type: "ListBots"
+} | {
+  type: "ResetBotToken",
+  id: number
+} | {
+  type: "DeleteBot",
+  id: number
+} | {
+  type: "SetUserBlocked",
+  id: number,
+  blocked: boolean
+} | {
+  type: "SetConversationMuted",
+  conversation: MessageRecipient,
+  muted: boolean
+} | {
+  type: "GetAuditLog",
+  // unix timestamps (seconds), inclusive
+  from: number | null,
+  to: number | null
 };
 
 export interface ServerUser {
   id: number,
   name: string,
-  online: boolean
+  online: boolean,
+  display_name: string | null,
+  status: string | null,
+  // time the avatar was last updated, if the user has one
+  avatar: number | null,
+  bot: boolean
 }
 
 export interface ServerGroup {
   id: number,
   name: string,
-  members: string[]
+  members: string[],
+  admins: string[],
+  owners: string[],
+  public: boolean
+}
+
+export type WebhookTrigger = {
+  Conversation: MessageRecipient
+} | {
+  Tag: string
+};
+
+export interface Webhook {
+  id: number,
+  owner: number,
+  url: string,
+  // only sent when the webhook is created
+  secret?: string,
+  trigger: WebhookTrigger,
+  global: boolean
+}
+
+export interface Reminder {
+  id: number,
+  user: number,
+  message: number,
+  time: number
+}
+
+// bots log in by sending `AuthenticateBot` with this token
+export interface Bot {
+  id: number,
+  owner: number,
+  // only sent when the bot is created or its token is reset
+  token?: string
+}
+
+// POST `{"text": "..."}` to `/hooks/{token}` to send a message as `bot`
+export interface IncomingWebhook {
+  // only sent when the webhook is created
+  token?: string,
+  bot: number,
+  recipient: MessageRecipient,
+  creator: number
+}
+
+export interface GroupInvite {
+  token: string,
+  group: number,
+  creator: number,
+  expires: number | null,
+  uses_left: number | null
 }
 
 export interface Message {
@@ -60,7 +260,32 @@ export interface Message {
   recipient: MessageRecipient,
   message: string,
   time: number,
-  tags: string[]
+  tags: string[],
+  forwarded: {
+    sender: number,
+    time: number
+  } | null
+}
+
+export type AuditEvent = {
+  Login: { user: number, remote_addr: string }
+} | {
+  UserCreated: { user: number }
+} | {
+  GroupCreated: { group: number, by: number }
+} | {
+  GroupEdited: { group: number, by: number, change: string }
+} | {
+  GroupDeleted: { group: number, by: number }
+} | {
+  MessageDeleted: { message: number, sender: number, by: number }
+} | {
+  AdminAction: { admin: number, action: string }
+};
+
+export interface AuditEntry {
+  time: number,
+  event: AuditEvent
 }
 
 // message from server->client
@@ -72,9 +297,25 @@ export type ServerMessage = {
   user_id: number,
   users: ServerUser[]
   groups: ServerGroup[]
+  blocked: ServerUser[],
+  muted: MessageRecipient[],
+  vapid_public_key: string | null
+} | {
+  type: "Disconnected",
+  reason: string
 } | {
   type: "UserAdded",
   user: ServerUser,
+} | {
+  type: "UserRenamed",
+  id: number,
+  name: string
+} | {
+  type: "UserUpdated",
+  user: ServerUser
+} | {
+  type: "UserDeleted",
+  id: number
 } | {
   type: "UserOnline",
   id: number
@@ -87,7 +328,9 @@ export type ServerMessage = {
   messages: Message[]
 } | {
   type: "MessageSent",
-  message: Message
+  message: Message,
+  // the conversation is muted, so don't notify
+  muted: boolean
 } | {
   type: "MessageEdited",
   id: number,
@@ -108,6 +351,101 @@ export type ServerMessage = {
 } | {
   type: "GroupDeleted",
   id: number
+} | {
+  type: "PublicChannels",
+  channels: ServerGroup[]
+} | {
+  type: "InviteCreated",
+  invite: GroupInvite
+} | {
+  type: "Invites",
+  group: number,
+  invites: GroupInvite[]
+} | {
+  type: "InviteRevoked",
+  token: string
+} | {
+  type: "AllGroups",
+  groups: ServerGroup[]
+} | {
+  type: "Stats",
+  stats: {
+    users: number,
+    groups: number,
+    messages: number,
+    invites: number,
+    avatars: number
+  },
+  sessions: number
+} | {
+  type: "AuditLog",
+  entries: AuditEntry[]
+} | {
+  type: "WebhookCreated",
+  webhook: Webhook
+} | {
+  type: "Webhooks",
+  webhooks: Webhook[]
+} | {
+  type: "WebhookDeleted",
+  id: number
+} | {
+  type: "IncomingWebhookCreated",
+  webhook: IncomingWebhook
+} | {
+  type: "IncomingWebhooks",
+  webhooks: IncomingWebhook[]
+} | {
+  type: "IncomingWebhookDeleted",
+  bot: number
+} | {
+  type: "ReminderCreated",
+  reminder: Reminder
+} | {
+  type: "Reminders",
+  reminders: Reminder[]
+} | {
+  type: "ReminderCancelled",
+  id: number
+} | {
+  // a reminder came due
+  type: "Reminder",
+  id: number,
+  message: Message
+} | {
+  type: "CommandResponse",
+  recipient: MessageRecipient,
+  text: string
+} | {
+  type: "Commands",
+  commands: { name: string, description: string }[]
+} | {
+  type: "CommandInvoked",
+  command: string,
+  args: string,
+  sender: number,
+  recipient: MessageRecipient
+} | {
+  // the server is going away. reconnect after this many seconds
+  type: "ServerShutdown",
+  reconnect_after: number
+} | {
+  type: "BotCreated",
+  bot: Bot
+} | {
+  type: "Bots",
+  bots: Bot[]
+} | {
+  type: "BotTokenReset",
+  bot: Bot
+} | {
+  type: "UserBlocked",
+  id: number,
+  blocked: boolean
+} | {
+  type: "ConversationMuted",
+  conversation: MessageRecipient,
+  muted: boolean
 };
 
 interface SocketEvents {
@@ -126,6 +464,8 @@ export default class Socket {
   private connectionRetry: number = 2;
   private retryPromise: Promise<void> | null = null;
   private retryResolve: () => void | null = null;
+  // don't reconnect before this time (ms), set when the server shuts down
+  private reconnectAt: number = 0;
   
   private listeners: {
     [Key in keyof SocketEvents]: ((...args: SocketEvents[Key]) => void)[];
@@ -143,6 +483,10 @@ export default class Socket {
     this.socket.addEventListener("message", e => {
       if (e.data instanceof ArrayBuffer) {
         const deserialized = decode(e.data) as ServerMessage;
+        if (deserialized.type === "ServerShutdown") {
+          this.connectionRetry = deserialized.reconnect_after;
+          this.reconnectAt = Date.now() + deserialized.reconnect_after * 1000;
+        }
         this.listeners.message.forEach(el => el(deserialized));
       }
     });
@@ -183,7 +527,7 @@ export default class Socket {
     this.retryPromise = promise;
     this.retryResolve = resolve;
 
-    this.initSocket();
+    setTimeout(() => this.initSocket(), Math.max(0, this.reconnectAt - Date.now()));
     return this.retryPromise;
   }
   
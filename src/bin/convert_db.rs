@@ -42,6 +42,8 @@ struct Message {
 }
 
 
+// lines that can't be read are skipped, not treated as the end of the file
+#[allow(clippy::lines_filter_map_ok)]
 fn main() {
     let mut args = env::args().skip(1);
     if args.len() != 2 {
@@ -82,7 +84,7 @@ fn main() {
         
         group_id_mappings.insert(
             group._id,
-            store.create_update_group(group.name, user_ids, None, user_id).unwrap()
+            store.create_update_group(group.name, user_ids, false, None, user_id).unwrap().0
         );
     }
 
@@ -114,7 +116,8 @@ fn main() {
             recipient,
             message: message.message,
             time: time as i64,
-            tags
+            tags,
+            forwarded: None
         };
 
         store.create_message(msg).unwrap();
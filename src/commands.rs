@@ -0,0 +1,408 @@
+//! Slash commands: messages starting with `/` are handled by the server instead of being sent as-is
+
+use std::{collections::{HashMap, HashSet}, fmt::Display, sync::{Arc, RwLock}};
+
+use chrono::Utc;
+
+use crate::store::{Message, MessageRecipient, Reminder, Store, StoreError};
+
+/// everything a command knows about where it was run
+pub struct CommandContext<'a> {
+    pub store: &'a Store,
+    pub sender: u16,
+    pub recipient: MessageRecipient,
+    /// users with a live session
+    pub online: &'a HashSet<u16>
+}
+
+impl CommandContext<'_> {
+    /// the name to show for a user: their display name if they have one
+    fn display_name(&self, user_id: u16) -> Result<String> {
+        Ok(match self.store.get_user_details(user_id)?.display_name {
+            Some(name) => name,
+            None => self.store.get_username_for_id(user_id)?.unwrap_or_default()
+        })
+    }
+}
+
+/// what should happen after a command runs
+#[derive(Debug)]
+pub enum CommandOutput {
+    /// send this text to the conversation as a normal message from the caller
+    Send(String),
+    /// show this text to just the caller
+    Ephemeral(String),
+    /// the tags of one of the caller's messages were changed
+    TagsEdited(u16, Message),
+    /// the caller will be reminded about a message
+    ReminderSet(u64, Reminder),
+    /// hand the command to a bot's live session
+    Forward { bot: u16 }
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Unknown(String),
+    /// command names are like usernames, but can also contain - and _
+    InvalidName,
+    Usage(&'static str),
+    /// the command couldn't run here
+    Failed(&'static str),
+    /// a bot already registered this name, or it's built in
+    NameTaken,
+    BotOffline,
+    StoreError(StoreError)
+}
+
+impl From<StoreError> for CommandError {
+    fn from(value: StoreError) -> Self {
+        Self::StoreError(value)
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "Unknown command /{name}"),
+            Self::InvalidName => write!(f, "Command names may only contain alphanumeric characters, - and _"),
+            Self::Usage(usage) => write!(f, "Usage: {usage}"),
+            Self::Failed(reason) => write!(f, "{reason}"),
+            Self::NameTaken => write!(f, "A command with that name already exists"),
+            Self::BotOffline => write!(f, "The bot for that command isn't connected"),
+            Self::StoreError(err) => err.fmt(f)
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, CommandError>;
+
+pub trait Command: Send + Sync {
+    fn description(&self) -> &str;
+
+    /// the bot that registered this command, if any. its commands go away when it disconnects
+    fn owner(&self) -> Option<u16> {
+        None
+    }
+
+    /// run the command with everything after its name, trimmed
+    fn run(&self, context: &CommandContext, args: &str) -> Result<CommandOutput>;
+}
+
+/// the longest command name bots can register
+const MAX_NAME_LENGTH: usize = 32;
+
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Err(CommandError::InvalidName)
+    } else {
+        Ok(())
+    }
+}
+
+/// split a message into a command name and its arguments, if it looks like a command
+///
+/// messages starting with `//` are not commands: they're sent with the first slash removed
+fn parse(message: &str) -> Option<(&str, &str)> {
+    let command = message.strip_prefix('/')?;
+    if command.starts_with('/') {
+        return None;
+    }
+    let (name, args) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    Some((name, args.trim()))
+}
+
+pub struct CommandRegistry {
+    commands: RwLock<HashMap<String, Arc<dyn Command>>>
+}
+
+impl CommandRegistry {
+    /// a registry with the built-in commands
+    pub fn new() -> Self {
+        let registry = Self { commands: RwLock::new(HashMap::new()) };
+        registry.register("me", Me);
+        registry.register("remind", Remind);
+        registry.register("shrug", Shrug);
+        registry.register("tag", Tag);
+        registry.register("who", Who);
+        registry
+    }
+
+    /// add a command. returns false if the name is taken
+    pub fn register(&self, name: &str, command: impl Command + 'static) -> bool {
+        let mut commands = self.commands.write().unwrap();
+        if commands.contains_key(name) {
+            return false;
+        }
+        commands.insert(name.to_owned(), Arc::new(command));
+        true
+    }
+
+    /// remove a command, e.g. a built-in one for a disabled feature
+    pub fn unregister(&self, name: &str) {
+        self.commands.write().unwrap().remove(name);
+    }
+
+    /// remove every command a bot registered
+    pub fn unregister_owner(&self, owner: u16) {
+        self.commands.write().unwrap().retain(|_, command| command.owner() != Some(owner));
+    }
+
+    /// (name, description) of every command, sorted by name
+    pub fn list(&self) -> Vec<(String, String)> {
+        let mut commands: Vec<_> = self.commands.read().unwrap()
+            .iter()
+            .map(|(name, command)| (name.clone(), command.description().to_owned()))
+            .collect();
+        commands.sort();
+        commands
+    }
+
+    /// split a message into the name of a registered command and its arguments. anything else
+    /// starting with a slash, like a path, is an ordinary message
+    pub fn parse<'a>(&self, message: &'a str) -> Option<(&'a str, &'a str)> {
+        parse(message).filter(|(name, _)| self.commands.read().unwrap().contains_key(*name))
+    }
+
+    /// run a command, as long as the caller could send a message to the conversation
+    pub fn run(&self, name: &str, context: &CommandContext, args: &str) -> Result<CommandOutput> {
+        let command = self.commands.read().unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| CommandError::Unknown(name.to_owned()))?;
+
+        match context.recipient {
+            MessageRecipient::User(user_id) => {
+                context.store.get_username_for_id(user_id)?.ok_or(StoreError::InvalidUserIds)?;
+            },
+            MessageRecipient::Group(group_id) => {
+                let members = context.store.get_group_members(group_id)?.ok_or(StoreError::InvalidGroupId)?;
+                if !members.contains(&context.sender) {
+                    return Err(StoreError::PermissionDenied.into());
+                }
+            }
+        }
+
+        command.run(context, args)
+    }
+}
+
+/// `/me waves`: an action in the third person
+struct Me;
+
+impl Command for Me {
+    fn description(&self) -> &str {
+        "Describe what you're doing"
+    }
+
+    fn run(&self, context: &CommandContext, args: &str) -> Result<CommandOutput> {
+        if args.is_empty() {
+            return Err(CommandError::Usage("/me <action>"));
+        }
+        Ok(CommandOutput::Send(format!("* {} {args}", context.display_name(context.sender)?)))
+    }
+}
+
+struct Shrug;
+
+impl Command for Shrug {
+    fn description(&self) -> &str {
+        "Append ¯\\_(ツ)_/¯ to your message"
+    }
+
+    fn run(&self, _context: &CommandContext, args: &str) -> Result<CommandOutput> {
+        Ok(CommandOutput::Send(format!("{args} ¯\\_(ツ)_/¯").trim_start().to_owned()))
+    }
+}
+
+/// `/tag a b`: add tags to the caller's last message in the conversation
+struct Tag;
+
+impl Command for Tag {
+    fn description(&self) -> &str {
+        "Tag your last message here"
+    }
+
+    fn run(&self, context: &CommandContext, args: &str) -> Result<CommandOutput> {
+        if args.is_empty() {
+            return Err(CommandError::Usage("/tag <tag> [tag...]"));
+        }
+
+        let (id, message) = context.store.get_last_message(context.sender, context.recipient, Some(context.sender))?
+            .ok_or(CommandError::Failed("Send a message before tagging it"))?;
+
+        let mut tags = message.tags;
+        for tag in args.split_whitespace() {
+            let tag = tag.trim_start_matches('#');
+            if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_owned());
+            }
+        }
+
+        let message = context.store.edit_message_tags(id, tags, context.sender)?
+            .ok_or(StoreError::InvalidMessageId)?;
+        Ok(CommandOutput::TagsEdited(id, message))
+    }
+}
+
+/// parse a delay like `30s`, `15m`, `2h`, `1d` or `1w` into seconds
+fn parse_delay(delay: &str) -> Option<i64> {
+    let unit = match delay.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None
+    };
+    let amount: i64 = delay[..delay.len() - 1].parse().ok()?;
+    amount.checked_mul(unit).filter(|delay| *delay > 0)
+}
+
+/// `/remind 2h`: remind the caller about the last message in the conversation
+struct Remind;
+
+impl Command for Remind {
+    fn description(&self) -> &str {
+        "Get reminded about the last message here"
+    }
+
+    fn run(&self, context: &CommandContext, args: &str) -> Result<CommandOutput> {
+        let delay = parse_delay(args).ok_or(CommandError::Usage("/remind <delay, like 30m, 2h or 1d>"))?;
+
+        let (id, _) = context.store.get_last_message(context.sender, context.recipient, None)?
+            .ok_or(CommandError::Failed("There are no messages here to be reminded about"))?;
+
+        let (reminder_id, reminder) = context.store.create_reminder(context.sender, id, Utc::now().timestamp() + delay)?;
+        Ok(CommandOutput::ReminderSet(reminder_id, reminder))
+    }
+}
+
+/// `/who`: list the members of the conversation
+struct Who;
+
+impl Command for Who {
+    fn description(&self) -> &str {
+        "List who's in this conversation"
+    }
+
+    fn run(&self, context: &CommandContext, _args: &str) -> Result<CommandOutput> {
+        let members: HashSet<u16> = match context.recipient {
+            MessageRecipient::User(user_id) => HashSet::from([context.sender, user_id]),
+            MessageRecipient::Group(group_id) => context.store.get_group_members(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+        };
+
+        let mut names = members.into_iter()
+            .map(|id| {
+                let name = context.display_name(id)?;
+                Ok(if context.online.contains(&id) { format!("{name} (online)") } else { name })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(CommandOutput::Ephemeral(format!("{} members: {}", names.len(), names.join(", "))))
+    }
+}
+
+/// a command registered by a bot. it's only available where the bot can read messages
+pub struct BotCommand {
+    pub bot: u16,
+    pub description: String
+}
+
+impl Command for BotCommand {
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn owner(&self) -> Option<u16> {
+        Some(self.bot)
+    }
+
+    fn run(&self, context: &CommandContext, _args: &str) -> Result<CommandOutput> {
+        let in_conversation = match context.recipient {
+            MessageRecipient::User(user_id) => user_id == self.bot,
+            MessageRecipient::Group(group_id) => context.store.get_group_members(group_id)?
+                .is_some_and(|members| members.contains(&self.bot))
+        };
+        if !in_conversation {
+            return Err(CommandError::Failed("Add the bot to this conversation to use its commands"));
+        }
+        if !context.online.contains(&self.bot) {
+            return Err(CommandError::BotOffline);
+        }
+        Ok(CommandOutput::Forward { bot: self.bot })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::{HashSet, BTreeSet}, path::PathBuf};
+
+    use super::{parse, parse_delay, validate_name, BotCommand, CommandContext, CommandError, CommandOutput, CommandRegistry};
+    use crate::store::{MessageRecipient, Store};
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse("/shrug"), Some(("shrug", "")));
+        assert!(validate_name("deploy-prod_2").is_ok());
+        assert!(validate_name("no spaces").is_err());
+        assert_eq!(parse_delay("90s"), Some(90));
+        assert_eq!(parse_delay("2h"), Some(7200));
+        assert_eq!(parse_delay("0m"), None);
+        assert_eq!(parse_delay("h"), None);
+        assert_eq!(parse("/tag  a b "), Some(("tag", "a b")));
+        assert_eq!(parse("//tag a"), None);
+        assert_eq!(parse("hi /tag"), None);
+
+        // only registered commands count
+        let registry = CommandRegistry::new();
+        assert_eq!(registry.parse("/shrug ok"), Some(("shrug", "ok")));
+        assert_eq!(registry.parse("/usr/bin/foo --help"), None);
+        assert_eq!(registry.parse("/nope"), None);
+    }
+
+    #[test]
+    fn builtin_commands() {
+        let store = Store::init::<PathBuf>(None).unwrap();
+        store.create_user("a".into()).unwrap();
+        store.create_user("b".into()).unwrap();
+        store.update_profile(0, Some("Alice".into()), None, None).unwrap();
+        let online = HashSet::from([1]);
+        let context = CommandContext { store: &store, sender: 0, recipient: MessageRecipient::User(1), online: &online };
+
+        let registry = CommandRegistry::new();
+        let run = |name, args| registry.run(name, &context, args);
+
+        assert!(matches!(run("me", "waves"), Ok(CommandOutput::Send(text)) if text == "* Alice waves"));
+        assert!(matches!(run("shrug", ""), Ok(CommandOutput::Send(text)) if text == "¯\\_(ツ)_/¯"));
+        assert!(matches!(run("who", ""), Ok(CommandOutput::Ephemeral(text)) if text == "2 members: Alice, b (online)"));
+        assert!(matches!(run("nope", ""), Err(CommandError::Unknown(_))));
+        // only where they could send a message
+        let group = CommandContext { recipient: MessageRecipient::Group(0), ..context };
+        store.create_update_group("g".into(), HashSet::from([1]), false, None, 1).unwrap();
+        assert!(matches!(registry.run("who", &group, ""), Err(CommandError::StoreError(_))));
+
+        // tags go on the caller's latest message
+        assert!(matches!(run("tag", "a"), Err(CommandError::Failed(_))));
+        store.send_message("first".into(), 0, MessageRecipient::User(1)).unwrap();
+        let (id, _) = store.send_message("second".into(), 0, MessageRecipient::User(1)).unwrap();
+        store.send_message("reply".into(), 1, MessageRecipient::User(0)).unwrap();
+        let Ok(CommandOutput::TagsEdited(tagged, message)) = run("tag", "#a b a") else { panic!("not tagged") };
+        assert_eq!(tagged, id);
+        assert_eq!(message.tags.into_iter().collect::<BTreeSet<_>>(), BTreeSet::from(["a".into(), "b".into()]));
+
+        // reminders are about the latest message from anyone
+        let Ok(CommandOutput::ReminderSet(_, reminder)) = run("remind", "1h") else { panic!("no reminder") };
+        assert_eq!(reminder.message, id + 1);
+        assert!(matches!(run("remind", "soon"), Err(CommandError::Usage(_))));
+
+        // bot commands can't shadow anything, and only work where the bot is
+        assert!(!registry.register("who", BotCommand { bot: 1, description: String::new() }));
+        assert!(registry.register("deploy", BotCommand { bot: 1, description: String::new() }));
+        assert!(matches!(run("deploy", ""), Ok(CommandOutput::Forward { bot: 1 })));
+        let context = CommandContext { recipient: MessageRecipient::User(0), sender: 1, ..context };
+        assert!(matches!(registry.run("deploy", &context, ""), Err(CommandError::Failed(_))));
+        registry.unregister_owner(1);
+        assert!(matches!(run("deploy", ""), Err(CommandError::Unknown(_))));
+    }
+}
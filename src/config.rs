@@ -0,0 +1,422 @@
+//! Server configuration: an optional TOML file, overridden by `STC_*` environment variables,
+//! overridden by command line flags
+
+use std::{collections::HashSet, fmt::Display, fs, io, path::PathBuf};
+
+use axum::http::HeaderValue;
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+use crate::{headers::DEFAULT_CONTENT_SECURITY_POLICY, listener::{ListenerBindAddrs, TlsPaths}, origins::AllowedOrigins};
+
+/// Lightweight messaging application
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// Addresses to listen on: a port on localhost, an IP address (random port), IP:PORT,
+    /// uds:PATH or systemd. `IP PORT` is also accepted for a single address
+    #[arg(value_name = "BIND_ADDR", conflicts_with = "bind")]
+    bind_args: Vec<String>,
+
+    /// Address to listen on (can be given more than once). Same forms as BIND_ADDR
+    #[arg(short, long, value_name = "ADDR", env = "STC_BIND", value_delimiter = ',')]
+    bind: Vec<String>,
+
+    /// TOML config file. Environment variables and flags override what it sets
+    #[arg(short, long, value_name = "FILE", env = "STC_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Persistent message/user store. An in-memory store is used if not set
+    #[arg(long, value_name = "PATH", env = "STC_STORE_PATH")]
+    store_path: Option<PathBuf>,
+
+    /// Directory the frontend is served from, instead of the copy built into the binary
+    /// (if there is one) [default: static]
+    #[arg(long, value_name = "DIR", env = "STC_STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+
+    /// Origins allowed to open a websocket, as CORS does not apply to websockets
+    #[arg(long, value_name = "ORIGIN", env = "STC_ALLOWED_ORIGINS", value_delimiter = ',')]
+    allowed_origins: Option<Vec<String>>,
+
+    /// Usernames that are always server admins
+    #[arg(long, value_name = "USERNAME", env = "STC_ADMINS", value_delimiter = ',')]
+    admins: Option<Vec<String>>,
+
+    /// PEM certificate chain to serve HTTPS with (needs --tls-key)
+    #[arg(long, value_name = "FILE", env = "STC_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key to serve HTTPS with (needs --tls-cert)
+    #[arg(long, value_name = "FILE", env = "STC_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// mailto: or https: contact for push services. Enables Web Push
+    #[arg(long, value_name = "CONTACT", env = "STC_VAPID_SUBJECT")]
+    vapid_subject: Option<String>,
+
+    /// Content-Security-Policy header for every response. Empty to leave it out
+    #[arg(long, value_name = "POLICY", env = "STC_CONTENT_SECURITY_POLICY")]
+    content_security_policy: Option<String>,
+
+    /// Log filter, e.g. `info` or `warn,send_to_computer=debug` [default: info]
+    #[arg(long, value_name = "FILTER", env = "RUST_LOG")]
+    log_level: Option<String>,
+
+    /// Messages each connection can send per second on average (0 for no limit)
+    #[arg(long, value_name = "N", env = "STC_RATE_LIMIT")]
+    rate_limit: Option<u32>,
+
+    /// Messages each connection can send in a burst
+    #[arg(long, value_name = "N", env = "STC_RATE_LIMIT_BURST")]
+    rate_limit_burst: Option<u32>,
+
+    /// Largest avatar users can upload, in bytes
+    #[arg(long, value_name = "BYTES", env = "STC_MAX_AVATAR_SIZE")]
+    max_avatar_size: Option<usize>,
+
+    /// Largest websocket message clients can send, in bytes
+    #[arg(long, value_name = "BYTES", env = "STC_MAX_WEBSOCKET_MESSAGE_SIZE")]
+    max_websocket_message_size: Option<usize>,
+
+    /// Let outgoing webhooks use http, and point at localhost or private networks
+    #[arg(long, env = "STC_ALLOW_PRIVATE_WEBHOOKS")]
+    allow_private_webhooks: bool,
+
+    /// Turn off a feature (can be given more than once)
+    #[arg(long, value_name = "FEATURE", env = "STC_DISABLE", value_delimiter = ',')]
+    disable: Vec<Feature>,
+}
+
+/// optional parts of the server that can be turned off
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Feature {
+    Push,
+    Webhooks,
+    Bots,
+    Reminders,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_avatar_size: usize,
+    pub max_websocket_message_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_avatar_size: 256 * 1024,
+            max_websocket_message_size: 1024 * 1024,
+        }
+    }
+}
+
+/// a token bucket for the messages each connection sends
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    /// how fast the bucket refills. 0 turns the limit off
+    pub per_second: u32,
+    /// how big the bucket is
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self { per_second: 10, burst: 50 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// Web Push notifications (also needs a VAPID subject)
+    pub push: bool,
+    /// incoming and outgoing webhooks
+    pub webhooks: bool,
+    /// bot accounts and the commands they register
+    pub bots: bool,
+    pub reminders: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self { push: true, webhooks: true, bots: true, reminders: true }
+    }
+}
+
+impl Features {
+    fn disable(&mut self, feature: Feature) {
+        match feature {
+            Feature::Push => self.push = false,
+            Feature::Webhooks => self.webhooks = false,
+            Feature::Bots => self.bots = false,
+            Feature::Reminders => self.reminders = false,
+        }
+    }
+}
+
+/// the config file. every key is optional
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    bind: Vec<String>,
+    store_path: Option<PathBuf>,
+    static_dir: Option<PathBuf>,
+    allowed_origins: Option<Vec<String>>,
+    admins: Vec<String>,
+    tls: Option<TlsPaths>,
+    vapid_subject: Option<String>,
+    content_security_policy: Option<String>,
+    log_level: Option<String>,
+    rate_limit: RateLimit,
+    limits: Limits,
+    features: Features,
+    allow_private_webhooks: bool,
+}
+
+/// the settings that are reloaded on SIGHUP. they apply to connections made after that
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeConfig {
+    pub allowed_origins: AllowedOrigins,
+    pub admins: HashSet<String>,
+    pub rate_limit: RateLimit,
+    pub log_level: String,
+}
+
+/// where the frontend is served from
+#[derive(Debug, PartialEq, Eq)]
+pub enum StaticFiles {
+    Dir(PathBuf),
+    /// built into the binary with the `embed-static` feature
+    #[cfg(feature = "embed-static")]
+    Embedded,
+}
+
+pub struct Config {
+    pub bind: ListenerBindAddrs,
+    pub store_path: Option<PathBuf>,
+    pub static_files: StaticFiles,
+    pub runtime: RuntimeConfig,
+    pub tls: Option<TlsPaths>,
+    /// only set if push notifications are enabled
+    pub vapid_subject: Option<String>,
+    pub content_security_policy: Option<String>,
+    pub limits: Limits,
+    pub features: Features,
+    /// outgoing webhooks can use http and private hosts
+    pub allow_private_webhooks: bool,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// the setting, and what's wrong with it
+    Invalid(&'static str, String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "Could not read config file {}: {err}", path.display()),
+            Self::Parse(path, err) => write!(f, "Invalid config file {}: {err}", path.display()),
+            Self::Invalid(setting, err) => write!(f, "Invalid {setting}: {err}"),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, ConfigError>;
+
+impl Config {
+    /// read the config file (if any) and combine it with the environment and flags
+    pub fn load(args: &Args) -> Result<Self> {
+        let file = match &args.config {
+            Some(path) => {
+                let contents = fs::read_to_string(path).map_err(|err| ConfigError::Read(path.clone(), err))?;
+                toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.clone(), err))?
+            },
+            None => ConfigFile::default(),
+        };
+        Self::resolve(args, file)
+    }
+
+    fn resolve(args: &Args, file: ConfigFile) -> Result<Self> {
+        let bind = [&args.bind_args, &args.bind, &file.bind].into_iter()
+            .find(|bind| !bind.is_empty())
+            .map_or(&[][..], Vec::as_slice);
+        let bind = ListenerBindAddrs::try_from(bind).map_err(|err| ConfigError::Invalid("bind address", err))?;
+
+        let static_files = match args.static_dir.clone().or(file.static_dir) {
+            // a directory that was asked for has to exist
+            Some(static_dir) if !static_dir.is_dir() => {
+                return Err(ConfigError::Invalid("static_dir", format!("{} is not a directory", static_dir.display())));
+            },
+            Some(static_dir) => StaticFiles::Dir(static_dir),
+            #[cfg(feature = "embed-static")]
+            None => StaticFiles::Embedded,
+            #[cfg(not(feature = "embed-static"))]
+            None => {
+                // the server still works without it (e.g. for bots), so only warn
+                let static_dir = PathBuf::from("static");
+                if !static_dir.is_dir() {
+                    log::warn!("{} is not a directory, so the web client won't be served", static_dir.display());
+                }
+                StaticFiles::Dir(static_dir)
+            },
+        };
+
+        let allowed_origins = args.allowed_origins.clone()
+            .or(file.allowed_origins)
+            .unwrap_or_else(|| vec!["http://localhost:8080".into(), "http://127.0.0.1:8080".into()]);
+        let allowed_origins = AllowedOrigins::parse(&allowed_origins).map_err(|err| ConfigError::Invalid("allowed_origins", err))?;
+        if allowed_origins.is_empty() {
+            return Err(ConfigError::Invalid("allowed_origins", "at least one origin is needed to connect".into()));
+        }
+
+        let admins = args.admins.as_ref().unwrap_or(&file.admins)
+            .iter()
+            .map(|admin| admin.trim().to_owned())
+            .filter(|admin| !admin.is_empty())
+            .collect();
+
+        // serve HTTPS directly if both a certificate and key are given
+        let tls = match (&args.tls_cert, &args.tls_key) {
+            (Some(cert), Some(key)) => Some(TlsPaths { cert: cert.clone(), key: key.clone() }),
+            (None, None) => file.tls,
+            _ => return Err(ConfigError::Invalid("tls", "the certificate and key must be set together".into())),
+        };
+
+        let mut features = file.features;
+        for feature in &args.disable {
+            features.disable(*feature);
+        }
+
+        // push notifications need a contact for the push services
+        let vapid_subject = args.vapid_subject.clone().or(file.vapid_subject).filter(|_| features.push);
+        if let Some(subject) = &vapid_subject {
+            if !subject.starts_with("mailto:") && !subject.starts_with("https:") {
+                return Err(ConfigError::Invalid("vapid_subject", "must be a mailto: or https: URL".into()));
+            }
+        }
+
+        let content_security_policy = args.content_security_policy.clone()
+            .or(file.content_security_policy)
+            .unwrap_or_else(|| DEFAULT_CONTENT_SECURITY_POLICY.into());
+        let content_security_policy = Some(content_security_policy.trim().to_owned()).filter(|policy| !policy.is_empty());
+        if content_security_policy.as_deref().is_some_and(|policy| HeaderValue::from_str(policy).is_err()) {
+            return Err(ConfigError::Invalid("content_security_policy", "must be a valid header value".into()));
+        }
+
+        let mut rate_limit = file.rate_limit;
+        if let Some(per_second) = args.rate_limit {
+            rate_limit.per_second = per_second;
+        }
+        if let Some(burst) = args.rate_limit_burst {
+            rate_limit.burst = burst;
+        }
+        if rate_limit.per_second > 0 && rate_limit.burst == 0 {
+            return Err(ConfigError::Invalid("rate_limit", "burst must be at least 1".into()));
+        }
+
+        let log_level = args.log_level.clone().or(file.log_level).unwrap_or_else(|| "info".into());
+
+        let mut limits = file.limits;
+        if let Some(max_avatar_size) = args.max_avatar_size {
+            limits.max_avatar_size = max_avatar_size;
+        }
+        if let Some(max_websocket_message_size) = args.max_websocket_message_size {
+            limits.max_websocket_message_size = max_websocket_message_size;
+        }
+        if limits.max_websocket_message_size <= limits.max_avatar_size {
+            return Err(ConfigError::Invalid("limits", "max_websocket_message_size must be larger than max_avatar_size".into()));
+        }
+
+        Ok(Self {
+            bind,
+            store_path: args.store_path.clone().or(file.store_path),
+            static_files,
+            runtime: RuntimeConfig { allowed_origins, admins, rate_limit, log_level },
+            tls,
+            vapid_subject,
+            content_security_policy,
+            limits,
+            features,
+            allow_private_webhooks: args.allow_private_webhooks || file.allow_private_webhooks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::{Args, Config, ConfigError, ConfigFile, Features, RateLimit};
+    use crate::origins::AllowedOrigins;
+
+    fn resolve(args: &[&str], file: &str) -> Result<Config, ConfigError> {
+        let args = Args::try_parse_from(["send-to-computer"].iter().chain(args)).unwrap();
+        let file: ConfigFile = toml::from_str(file).unwrap();
+        Config::resolve(&args, file)
+    }
+
+    #[test]
+    fn config_layers() {
+        let file = r#"
+            bind = ["127.0.0.1:9000"]
+            allowed_origins = ["https://chat.example.com"]
+            admins = ["alice", " "]
+            vapid_subject = "mailto:admin@example.com"
+
+            [rate_limit]
+            per_second = 2
+
+            [limits]
+            max_avatar_size = 1000
+
+            [features]
+            bots = false
+        "#;
+        let config = resolve(&[], file).unwrap();
+        assert_eq!(config.bind.0.len(), 1);
+        assert_eq!(config.runtime.allowed_origins, AllowedOrigins::parse(&["https://chat.example.com"]).unwrap());
+        assert_eq!(config.runtime.admins.len(), 1);
+        assert_eq!(config.runtime.rate_limit, RateLimit { per_second: 2, burst: 50 });
+        assert_eq!(config.runtime.log_level, "info");
+        assert!(config.content_security_policy.is_some());
+        assert_eq!(config.limits.max_avatar_size, 1000);
+        assert_eq!(config.features, Features { bots: false, ..Default::default() });
+        assert!(!config.allow_private_webhooks);
+
+        // flags win over the file
+        let config = resolve(&["--allowed-origins", "http://a.test,http://b.test:8080", "--disable", "push", "--max-avatar-size", "10"], file).unwrap();
+        assert_eq!(config.runtime.allowed_origins, AllowedOrigins::parse(&["http://a.test", "http://b.test:8080"]).unwrap());
+        assert_eq!(config.vapid_subject, None);
+        assert!(!config.features.bots && !config.features.push);
+        assert_eq!(config.limits.max_avatar_size, 10);
+        assert!(resolve(&["--allow-private-webhooks"], file).unwrap().allow_private_webhooks);
+
+        // the legacy `ip port` form
+        let config = resolve(&["0.0.0.0", "8080"], "").unwrap();
+        assert_eq!(config.bind.0.len(), 1);
+        assert!(Args::try_parse_from(["send-to-computer", "8080", "--bind", "8081"]).is_err());
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(toml::from_str::<ConfigFile>("allowed_origin = []").is_err());
+        assert!(matches!(resolve(&[], "allowed_origins = []"), Err(ConfigError::Invalid("allowed_origins", _))));
+        assert!(matches!(resolve(&[], r#"allowed_origins = ["https://example.com/"]"#), Err(ConfigError::Invalid("allowed_origins", _))));
+        assert!(matches!(resolve(&["--bind", "nope"], ""), Err(ConfigError::Invalid("bind address", _))));
+        assert!(matches!(resolve(&["--tls-cert", "cert.pem"], ""), Err(ConfigError::Invalid("tls", _))));
+        assert!(matches!(resolve(&["--vapid-subject", "admin@example.com"], ""), Err(ConfigError::Invalid("vapid_subject", _))));
+        assert!(matches!(resolve(&["--static-dir", "does-not-exist"], ""), Err(ConfigError::Invalid("static_dir", _))));
+        assert!(matches!(resolve(&["--max-avatar-size", "2000000"], ""), Err(ConfigError::Invalid("limits", _))));
+        assert!(matches!(resolve(&["--rate-limit-burst", "0"], ""), Err(ConfigError::Invalid("rate_limit", _))));
+        assert!(matches!(resolve(&[], r#"content_security_policy = "default-src\nx""#), Err(ConfigError::Invalid("content_security_policy", _))));
+        assert_eq!(resolve(&["--content-security-policy", ""], "").unwrap().content_security_policy, None);
+        assert!(resolve(&["--rate-limit", "0", "--rate-limit-burst", "0"], "").is_ok());
+    }
+}
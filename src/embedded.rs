@@ -0,0 +1,96 @@
+//! The frontend, built into the binary by `build.rs` with the `embed-static` feature
+
+use axum::{
+    http::{
+        header::{ACCEPT_ENCODING, ALLOW, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH, VARY},
+        HeaderMap, Method, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+};
+
+struct EmbeddedFile {
+    /// relative to `static/`
+    path: &'static str,
+    content_type: &'static str,
+    /// quoted, ready for the header
+    etag: &'static str,
+    data: &'static [u8],
+    gzip: Option<&'static [u8]>,
+    br: Option<&'static [u8]>,
+}
+
+static FILES: &[EmbeddedFile] = include!(concat!(env!("OUT_DIR"), "/embedded_static.rs"));
+
+/// whether the client accepts an encoding, going by `Accept-Encoding`
+fn accepts(headers: &HeaderMap, encoding: &str) -> bool {
+    headers.get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|accepted| {
+            let mut parts = accepted.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            // `q=0` means "not this one"
+            let refused = parts.any(|param| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+            name.eq_ignore_ascii_case(encoding) && !refused
+        })
+}
+
+/// whether the client's cached copy is still current
+fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag))
+}
+
+/// serve a file from the embedded `static/`, like `ServeDir` would
+pub async fn serve(method: Method, uri: Uri, headers: HeaderMap) -> Response {
+    if method != Method::GET && method != Method::HEAD {
+        return (StatusCode::METHOD_NOT_ALLOWED, [(ALLOW, "GET, HEAD")]).into_response();
+    }
+
+    // directories are served by their index.html
+    let mut path = uri.path().trim_start_matches('/').to_owned();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    }
+    let Some(file) = FILES.iter().find(|file| file.path == path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let common = [(ETAG, file.etag), (VARY, "accept-encoding")];
+    if not_modified(&headers, file.etag) {
+        return (StatusCode::NOT_MODIFIED, common).into_response();
+    }
+
+    let compressed = [("br", file.br), ("gzip", file.gzip)]
+        .into_iter()
+        .find_map(|(encoding, data)| data.filter(|_| accepts(&headers, encoding)).map(|data| (encoding, data)));
+    match compressed {
+        Some((encoding, data)) => (common, [(CONTENT_TYPE, file.content_type), (CONTENT_ENCODING, encoding)], data).into_response(),
+        None => (common, [(CONTENT_TYPE, file.content_type)], file.data).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::{ACCEPT_ENCODING, IF_NONE_MATCH}, HeaderMap, HeaderValue};
+
+    use super::{accepts, not_modified, FILES};
+
+    #[test]
+    fn embedded_files() {
+        let index = FILES.iter().find(|file| file.path == "index.html").unwrap();
+        assert_eq!(index.content_type, "text/html; charset=utf-8");
+        assert!(index.gzip.is_some() && index.br.is_some());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate;q=0.5, br;q=0"));
+        assert!(accepts(&headers, "gzip"));
+        assert!(!accepts(&headers, "br"));
+
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_str(&format!("\"abc\", W/{}", index.etag)).unwrap());
+        assert!(not_modified(&headers, index.etag));
+        assert!(!not_modified(&headers, "\"abc1\""));
+    }
+}
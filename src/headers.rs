@@ -0,0 +1,97 @@
+//! Compression, cache policy and security headers for HTTP responses
+
+use axum::{
+    extract::Request,
+    http::{
+        header::{self, HeaderName, CACHE_CONTROL},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+    Router,
+};
+use tower_http::{compression::CompressionLayer, set_header::SetResponseHeaderLayer};
+
+/// only allows what the frontend needs: its own scripts, styles (lit sets some inline), the
+/// websocket, and avatars
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; img-src 'self' data: blob:; style-src 'self' 'unsafe-inline'; object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'";
+
+/// for files whose name changes with their content
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// has to be checked every time, so updates are picked up right away
+const NO_CACHE: &str = "no-cache";
+
+/// a name like `main.1a2b3c4d.js`, where the part before the extension is a hash of the content
+fn is_hashed(name: &str) -> bool {
+    let mut parts = name.rsplit('.');
+    let _extension = parts.next();
+    let hash = parts.next().unwrap_or_default();
+    parts.next().is_some() && hash.len() >= 8 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn cache_policy(path: &str) -> &'static str {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    match name {
+        name if is_hashed(name) => IMMUTABLE,
+        // the entry points, and anything else whose name stays the same when it changes.
+        // revalidating (ETag or Last-Modified) still spares the download when nothing changed
+        _ => NO_CACHE,
+    }
+}
+
+/// middleware for static files: set Cache-Control based on the path
+pub async fn static_cache_control(request: Request, next: Next) -> Response {
+    let policy = cache_policy(request.uri().path());
+    let mut response = next.run(request).await;
+    if response.status().is_success() || response.status().is_redirection() {
+        response.headers_mut().entry(CACHE_CONTROL).or_insert(HeaderValue::from_static(policy));
+    }
+    response
+}
+
+/// compress responses and add security headers to all of them. the policy has already been
+/// validated by the config
+pub fn harden(app: Router, content_security_policy: Option<&str>, https: bool) -> Router {
+    let mut headers = vec![
+        (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+        (header::REFERRER_POLICY, HeaderValue::from_static("no-referrer")),
+        (HeaderName::from_static("cross-origin-opener-policy"), HeaderValue::from_static("same-origin")),
+        (HeaderName::from_static("permissions-policy"), HeaderValue::from_static("camera=(), microphone=(), geolocation=()")),
+    ];
+    if let Some(policy) = content_security_policy {
+        let policy = HeaderValue::from_str(policy).expect("the policy is a valid header value");
+        headers.push((header::CONTENT_SECURITY_POLICY, policy));
+    }
+    // only when we're the ones terminating TLS - a reverse proxy should decide this itself
+    if https {
+        headers.push((header::STRICT_TRANSPORT_SECURITY, HeaderValue::from_static("max-age=31536000")));
+    }
+
+    headers.into_iter()
+        .fold(app, |app, (name, value)| app.layer(SetResponseHeaderLayer::if_not_present(name, value)))
+        // embedded files are already compressed, and images are skipped
+        .layer(CompressionLayer::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cache_policy, is_hashed, IMMUTABLE, NO_CACHE};
+
+    #[test]
+    fn cache_policies() {
+        assert!(is_hashed("main.1a2b3c4d.js"));
+        assert!(is_hashed("chunk.abc.0123456789abcdef.css"));
+        assert!(!is_hashed("main.js"));
+        assert!(!is_hashed("1a2b3c4d.js"));
+        assert!(!is_hashed("main.1a2b.js"));
+        assert!(!is_hashed("icon-192.png"));
+
+        assert_eq!(cache_policy("/"), NO_CACHE);
+        assert_eq!(cache_policy("/index.html"), NO_CACHE);
+        assert_eq!(cache_policy("/service-worker.js"), NO_CACHE);
+        assert_eq!(cache_policy("/main.1a2b3c4d.js"), IMMUTABLE);
+        assert_eq!(cache_policy("/icons/icon-192.png"), NO_CACHE);
+        assert_eq!(cache_policy("/main.js"), NO_CACHE);
+    }
+}
@@ -1,78 +1,227 @@
 use std::{
-    env::Args,
+    env,
+    future::pending,
     io,
-    net::{AddrParseError, IpAddr, Ipv4Addr},
-    num::ParseIntError,
+    net::{self, IpAddr, Ipv4Addr, SocketAddr},
+    os::{fd::{FromRawFd, OwnedFd, RawFd}, unix},
+    path::PathBuf,
+    process,
+    sync::{Arc, OnceLock, RwLock},
+    time::Duration,
 };
 
 use axum::Router;
-use hyper::service::service_fn;
+use hyper::{body::Incoming, service::service_fn, Request};
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     server::conn::auto::Builder as ServerBuilder,
 };
-use log::{error, info};
+use futures_util::future::try_join_all;
+use log::{error, info, warn};
+use serde::Deserialize;
 use tokio::{
     fs::remove_file,
-    net::{TcpListener, UnixListener}, select, signal::unix::{signal, SignalKind},
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener}, pin, select, signal::unix::{signal, SignalKind},
+    time::timeout,
 };
-use tokio_util::net::Listener;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::sign::any_supported_type,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tokio_util::{net::Listener, sync::CancellationToken, task::TaskTracker};
 use tower::Service;
 
 const LOCALHOST_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
 
-/// A TCP address or Unix Socket address
+/// how long a client gets to finish the TLS handshake, so idle connections can't pile up
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// the first file descriptor passed by systemd
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A TCP address or Unix Socket address, or a socket passed by systemd
 pub enum ListenerBindAddr {
     TcpSocket(IpAddr, u16),
     UnixSocket(String),
+    /// already bound and listening, from `LISTEN_FDS`
+    Systemd(RawFd),
+}
+
+/// the listening sockets passed to this process with systemd socket activation, if any
+///
+/// the environment is only read once and then cleared, like `sd_listen_fds(1)` does, so processes we
+/// start don't think the sockets are theirs. the config is parsed again on reload, so the result is kept
+fn listen_fds() -> Option<Vec<RawFd>> {
+    static LISTEN_FDS: OnceLock<Option<Vec<RawFd>>> = OnceLock::new();
+    LISTEN_FDS.get_or_init(|| {
+        let fds = read_listen_fds();
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(var);
+        }
+        fds
+    }).clone()
+}
+
+fn read_listen_fds() -> Option<Vec<RawFd>> {
+    // make sure they were meant for us and not our parent
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if pid != process::id() {
+        return None;
+    }
+    let count: RawFd = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    Some((SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count).collect())
+}
+
+/// every socket passed by systemd
+fn systemd_sockets() -> Result<Vec<ListenerBindAddr>, String> {
+    listen_fds()
+        .filter(|fds| !fds.is_empty())
+        .map(|fds| fds.into_iter().map(ListenerBindAddr::Systemd).collect())
+        .ok_or_else(|| "No sockets were passed by systemd (LISTEN_FDS)".into())
 }
 
-impl TryFrom<Args> for ListenerBindAddr {
+impl ListenerBindAddr {
+    /// parse a single address argument. `systemd` stands for every socket systemd passed
+    fn parse_arg(arg: &str) -> Result<Vec<Self>, String> {
+        if arg == "systemd" {
+            systemd_sockets()
+        } else if let Some(uds_path) = arg.strip_prefix("uds:") {
+            // if it starts with uds:, it's a unix socket
+            Ok(vec![ListenerBindAddr::UnixSocket(uds_path.into())])
+        } else if let Ok(port) = arg.parse() {
+            // if it's a valid u16, it's just a port on localhost
+            Ok(vec![ListenerBindAddr::TcpSocket(LOCALHOST_V4, port)])
+        } else if let Ok(ip_addr) = arg.parse() {
+            // if it's a valid ip addr, it's a random port on the address
+            Ok(vec![ListenerBindAddr::TcpSocket(ip_addr, 0)])
+        } else if let Ok(socket_addr) = arg.parse::<SocketAddr>() {
+            // host:port (or [v6 host]:port)
+            Ok(vec![ListenerBindAddr::TcpSocket(socket_addr.ip(), socket_addr.port())])
+        } else {
+            Err(format!(
+                "Invalid argument: {arg}. Must be a valid UDS path, port, ip address, or ip address:port"
+            ))
+        }
+    }
+}
+
+/// all of the addresses to listen on
+pub struct ListenerBindAddrs(pub Vec<ListenerBindAddr>);
+
+impl TryFrom<&[String]> for ListenerBindAddrs {
     type Error = String;
 
-    fn try_from(all_args: Args) -> Result<Self, Self::Error> {
-        // skip the executable name
-        let mut args = all_args.skip(1);
-        match args.len() {
-            0 => {
+    fn try_from(args: &[String]) -> Result<Self, Self::Error> {
+        if args.is_empty() {
+            return if listen_fds().is_some() {
+                // started with socket activation
+                systemd_sockets().map(Self)
+            } else {
                 // no arguments provided - localhost on a random port
-                Ok(ListenerBindAddr::TcpSocket(LOCALHOST_V4, 0))
-            }
-            1 => {
-                // unix socket or ip addr or port
-                let arg = args.next().unwrap();
-
-                if let Some(uds_path) = arg.strip_prefix("uds:") {
-                    // if it starts with uds:, it's a unix socket
-                    Ok(ListenerBindAddr::UnixSocket(uds_path.into()))
-                } else if let Ok(port) = arg.parse() {
-                    // if it's a valid u16, it's just a port on localhost
-                    Ok(ListenerBindAddr::TcpSocket(LOCALHOST_V4, port))
-                } else if let Ok(ip_addr) = arg.parse() {
-                    // if it's a valid ip addr, it's a random port on the address
-                    Ok(ListenerBindAddr::TcpSocket(ip_addr, 0))
-                } else {
-                    Err(format!(
-                        "Invalid argument: {arg}. Must be a valid UDS path, port, or ip address"
-                    ))
-                }
+                Ok(Self(vec![ListenerBindAddr::TcpSocket(LOCALHOST_V4, 0)]))
+            };
+        }
+
+        // `ip port` is a single address, as it always has been
+        if let [ip_addr, port] = args {
+            if let (Ok(ip_addr), Ok(port)) = (ip_addr.parse(), port.parse()) {
+                return Ok(Self(vec![ListenerBindAddr::TcpSocket(ip_addr, port)]));
             }
-            2 => {
-                // ip addr + port
-                let ip_addr = args.next().unwrap();
-                let port = args.next().unwrap();
+        }
 
-                // parse the address and port
-                let ip_addr = ip_addr.parse().map_err(|e: AddrParseError| e.to_string())?;
-                let port = port.parse().map_err(|e: ParseIntError| e.to_string())?;
+        // the systemd sockets can only be taken once
+        if args.iter().filter(|arg| *arg == "systemd").count() > 1 {
+            return Err("systemd can only be given once".into());
+        }
 
-                Ok(ListenerBindAddr::TcpSocket(ip_addr, port))
-            }
-            _ => Err("Too many arguments provided".into()),
+        let mut addrs = vec![];
+        for arg in args {
+            addrs.extend(ListenerBindAddr::parse_arg(arg)?);
         }
+        Ok(Self(addrs))
+    }
+}
+
+/// PEM certificate chain and private key files to serve HTTPS with
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// resolves to the most recently loaded certificate, so it can be replaced without restarting
+#[derive(Debug)]
+struct ReloadableCert {
+    paths: TlsPaths,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    fn new(paths: TlsPaths) -> io::Result<Self> {
+        let current = RwLock::new(Arc::new(Self::load(&paths)?));
+        Ok(Self { paths, current })
+    }
+
+    fn load(paths: &TlsPaths) -> io::Result<CertifiedKey> {
+        let invalid = |path: &PathBuf, err: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {err}", path.display()))
+        };
+
+        let certs = CertificateDer::pem_file_iter(&paths.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| invalid(&paths.cert, err.to_string()))?;
+        if certs.is_empty() {
+            return Err(invalid(&paths.cert, "no certificates found".into()));
+        }
+        let key = PrivateKeyDer::from_pem_file(&paths.key)
+            .map_err(|err| invalid(&paths.key, err.to_string()))?;
+        let key = any_supported_type(&key).map_err(|err| invalid(&paths.key, err.to_string()))?;
+
+        Ok(CertifiedKey::new(certs, key))
+    }
+
+    /// load the files again. the old certificate stays in use if they're invalid
+    fn reload(&self) -> io::Result<()> {
+        let new = Self::load(&self.paths)?;
+        *self.current.write().unwrap() = Arc::new(new);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
     }
 }
 
+struct Tls {
+    acceptor: TlsAcceptor,
+    cert: Arc<ReloadableCert>,
+}
+
+impl Tls {
+    fn new(paths: TlsPaths) -> io::Result<Self> {
+        let cert = Arc::new(ReloadableCert::new(paths)?);
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(cert.clone());
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Self { acceptor: TlsAcceptor::from(Arc::new(config)), cert })
+    }
+}
+
+/// the address of the peer that made a request, added to each request's extensions
+#[derive(Clone, Debug)]
+pub struct RemoteAddr(pub String);
+
 /// basically a wrapper trait around `Display` to get around orphan rules
 trait SocketDisplay {
     fn socket_display(&self) -> String;
@@ -94,7 +243,52 @@ impl SocketDisplay for core::net::SocketAddr {
     }
 }
 
-async fn serve_router<T>(app: Router, mut listener: T) -> io::Result<()>
+/// wait for SIGTERM or SIGINT
+pub async fn shutdown_signal() -> io::Result<()> {
+    let mut term_signal = signal(SignalKind::terminate())?;
+    let mut interrupt_signal = signal(SignalKind::interrupt())?;
+    select! {
+        _ = term_signal.recv() => {},
+        _ = interrupt_signal.recv() => {},
+    }
+    Ok(())
+}
+
+/// serve HTTP (with upgrades) on a single connection until it closes or we shut down
+async fn serve_connection<I>(socket: I, app: Router, remote_addr: RemoteAddr, shutdown: CancellationToken)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // setup the hyper socket and service
+    let socket = TokioIo::new(socket);
+    let hyper_service = service_fn(move |mut req: Request<Incoming>| {
+        req.extensions_mut().insert(remote_addr.clone());
+        app.clone().call(req)
+    });
+    // serve the connection
+    let builder = ServerBuilder::new(TokioExecutor::new());
+    let conn = builder.serve_connection_with_upgrades(socket, hyper_service);
+    pin!(conn);
+    let result = select! {
+        result = conn.as_mut() => result,
+        _ = shutdown.cancelled() => {
+            // finish the requests in progress, but don't take any more
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(err) = result {
+        error!("Failed to serve connection: {err}");
+    }
+}
+
+async fn serve_router<T>(
+    app: Router,
+    mut listener: T,
+    tls: Option<Arc<Tls>>,
+    shutdown: CancellationToken,
+    shutdown_timeout: Duration,
+) -> io::Result<()>
 where
     T: Listener,
     <T as Listener>::Io: Unpin + Send + 'static,
@@ -109,46 +303,138 @@ where
             .socket_display()
     );
 
-    let mut term_signal = signal(SignalKind::terminate())?;
+    let connections = TaskTracker::new();
 
     loop {
         select! {
             conn = listener.accept() => {
-                let (socket, _addr) = conn?;
+                let (socket, addr) = conn?;
+                let remote_addr = RemoteAddr(addr.socket_display());
                 let service = app.clone();
+                let acceptor = tls.as_ref().map(|tls| tls.acceptor.clone());
+                let shutdown = shutdown.clone();
                 // new task for each connection
-                tokio::spawn(async move {
-                    // setup the hyper socket and service
-                    let socket = TokioIo::new(socket);
-                    let hyper_service = service_fn(move |req| service.clone().call(req));
-                    // serve the connection
-                    if let Err(err) = ServerBuilder::new(TokioExecutor::new())
-                        .serve_connection_with_upgrades(socket, hyper_service)
-                        .await
-                    {
-                        error!("Failed to serve connection: {err}");
+                connections.spawn(async move {
+                    match acceptor {
+                        // the handshake happens here so it can't hold up other connections
+                        Some(acceptor) => match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                            Ok(Ok(socket)) => serve_connection(socket, service, remote_addr, shutdown).await,
+                            Ok(Err(err)) => warn!("TLS handshake with {} failed: {err}", remote_addr.0),
+                            Err(_) => warn!("TLS handshake with {} timed out", remote_addr.0),
+                        },
+                        None => serve_connection(socket, service, remote_addr, shutdown).await,
                     }
                 });
             },
-            _ = term_signal.recv() => {
-                break Ok(());
+            _ = shutdown.cancelled() => {
+                break;
             }
         }
     }
+
+    // stop accepting, and let the open connections finish
+    drop(listener);
+    connections.close();
+    if timeout(shutdown_timeout, connections.wait()).await.is_err() {
+        warn!("{} connections were still open at shutdown", connections.len());
+    }
+    Ok(())
+}
+
+/// reload the TLS certificate whenever we get SIGHUP. never returns if there's no TLS
+async fn reload_on_hangup(tls: Option<Arc<Tls>>) -> io::Result<()> {
+    let Some(tls) = tls else {
+        return pending().await;
+    };
+
+    let mut hangup_signal = signal(SignalKind::hangup())?;
+    loop {
+        hangup_signal.recv().await;
+        // existing connections keep the certificate they started with
+        match tls.cert.reload() {
+            Ok(()) => info!("Reloaded TLS certificate"),
+            Err(err) => error!("Could not reload TLS certificate: {err}"),
+        }
+    }
+}
+
+/// a listener that has been bound, but isn't being served yet
+enum BoundListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
 }
 
-/// serve an axum `Router` on the specific `ListenerBindAddr`
-pub async fn serve(app: Router, addr: ListenerBindAddr) -> io::Result<()> {
-    match addr {
-        ListenerBindAddr::TcpSocket(ip_addr, port) => {
-            let listener = TcpListener::bind((ip_addr, port)).await?;
-            serve_router(app, listener).await
+impl BoundListener {
+    async fn bind(addr: ListenerBindAddr) -> io::Result<Self> {
+        match addr {
+            ListenerBindAddr::TcpSocket(ip_addr, port) => {
+                Ok(Self::Tcp(TcpListener::bind((ip_addr, port)).await?))
+            }
+            ListenerBindAddr::UnixSocket(path) => {
+                // remove the unix socket
+                let _ = remove_file(&path).await;
+                Ok(Self::Unix(UnixListener::bind(path)?))
+            }
+            ListenerBindAddr::Systemd(fd) => {
+                // SAFETY: systemd passed us this fd and nothing else uses it
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+                // it can be either kind of socket: only a TCP socket has an IP address
+                let listener = net::TcpListener::from(fd);
+                if listener.local_addr().is_ok() {
+                    listener.set_nonblocking(true)?;
+                    Ok(Self::Tcp(TcpListener::from_std(listener)?))
+                } else {
+                    let listener = unix::net::UnixListener::from(OwnedFd::from(listener));
+                    listener.set_nonblocking(true)?;
+                    Ok(Self::Unix(UnixListener::from_std(listener)?))
+                }
+            }
         }
-        ListenerBindAddr::UnixSocket(path) => {
-            // remove the unix socket
-            let _ = remove_file(&path).await;
-            let listener = UnixListener::bind(path)?;
-            serve_router(app, listener).await
+    }
+
+    /// `tls` only applies to TCP listeners: unix sockets are local, and whatever connects to them
+    /// (usually a reverse proxy) speaks plain HTTP
+    async fn serve(
+        self,
+        app: Router,
+        tls: Option<Arc<Tls>>,
+        shutdown: CancellationToken,
+        shutdown_timeout: Duration,
+    ) -> io::Result<()> {
+        match self {
+            Self::Tcp(listener) => serve_router(app, listener, tls, shutdown, shutdown_timeout).await,
+            Self::Unix(listener) => serve_router(app, listener, None, shutdown, shutdown_timeout).await,
         }
     }
 }
+
+/// serve an axum `Router` on every `ListenerBindAddr` at once. TCP listeners use HTTPS if `tls` is given
+///
+/// once `shutdown` is cancelled, open connections get up to `shutdown_timeout` to finish
+pub async fn serve(
+    app: Router,
+    addrs: ListenerBindAddrs,
+    tls: Option<TlsPaths>,
+    shutdown: CancellationToken,
+    shutdown_timeout: Duration,
+) -> io::Result<()> {
+    // fail before binding if the certificate can't be loaded
+    let tls = tls.map(Tls::new).transpose()?.map(Arc::new);
+
+    // bind everything first, so nothing is served if any address is unusable
+    let mut listeners = vec![];
+    for addr in addrs.0 {
+        listeners.push(BoundListener::bind(addr).await?);
+    }
+
+    let servers = try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| listener.serve(app.clone(), tls.clone(), shutdown.clone(), shutdown_timeout)),
+    );
+    select! {
+        result = servers => result.map(|_| ()),
+        result = reload_on_hangup(tls) => result,
+    }
+}
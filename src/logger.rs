@@ -0,0 +1,47 @@
+//! env_logger, but the filter can be replaced while the server is running
+
+use std::sync::{OnceLock, RwLock};
+
+use env_logger::{Builder, Logger};
+use log::{Log, Metadata, Record, SetLoggerError};
+
+struct ReloadableLogger {
+    current: RwLock<Logger>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.current.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.current.read().unwrap().log(record);
+    }
+
+    fn flush(&self) {
+        self.current.read().unwrap().flush();
+    }
+}
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+/// `filter` uses the same syntax as `RUST_LOG`
+fn build(filter: &str) -> Logger {
+    Builder::new().parse_filters(filter).build()
+}
+
+pub fn init(filter: &str) -> Result<(), SetLoggerError> {
+    let logger = LOGGER.get_or_init(|| ReloadableLogger { current: RwLock::new(build(filter)) });
+    log::set_logger(logger)?;
+    log::set_max_level(logger.current.read().unwrap().filter());
+    Ok(())
+}
+
+/// replace the filter for everything logged from now on
+pub fn set_filter(filter: &str) {
+    if let Some(logger) = LOGGER.get() {
+        let new = build(filter);
+        log::set_max_level(new.filter());
+        *logger.current.write().unwrap() = new;
+    }
+}
@@ -1,63 +1,105 @@
-use std::{borrow::Cow, env::{self, args}, path::PathBuf, sync::Arc};
+use std::{process, sync::Arc, time::Duration};
 
-use axum::{extract::{State, WebSocketUpgrade}, http::{header::ORIGIN, HeaderMap, StatusCode}, response::IntoResponse, routing::get, Router};
-use env_logger::Env;
-use listener::serve;
-use log::{error, info};
+use axum::{extract::{DefaultBodyLimit, Path, State, WebSocketUpgrade}, Extension, http::{header::{CACHE_CONTROL, CONTENT_TYPE, HOST, ORIGIN}, HeaderMap, StatusCode}, middleware, response::IntoResponse, routing::{get, post}, Json, Router};
+use clap::Parser;
+use config::{Args, Config, StaticFiles};
+use listener::{serve, shutdown_signal, RemoteAddr};
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::{signal::unix::{signal, SignalKind}, task::spawn_blocking};
+use tokio_util::sync::CancellationToken;
 use tower_http::services::ServeDir;
 use websocket::{WsHandler, WsState};
 
+mod commands;
+mod config;
+#[cfg(feature = "embed-static")]
+mod embedded;
+mod headers;
 mod listener;
+mod logger;
+mod origins;
+mod push;
 mod store;
+mod targets;
+mod webhooks;
 mod websocket;
 
+/// how long open connections and store writes get to finish when shutting down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 struct FullState {
-    ws_state: Arc<WsState>,
-    allowed_origins: &'static [Cow<'static, str>]
+    ws_state: Arc<WsState>
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-
-    let store_path = env::var("STC_STORE_PATH").ok().map(PathBuf::from);
-    let allowed_origins = env::var("STC_ALLOWED_ORIGINS").ok()
-        .map(|v| v.split(',').map(|a| Cow::Owned(a.to_owned())).collect())
-        .unwrap_or_else(|| vec!["http://localhost:8080".into(), "http://127.0.0.1:8080".into()]);
+    // until the config says otherwise
+    logger::init("info").expect("no other logger is set");
 
-    // leak the allowed origins - they live for static
-    let allowed_origins = Box::leak(allowed_origins.into_boxed_slice()) as &'static [Cow<str>];
+    let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{err}");
+            process::exit(1);
+        }
+    };
+    logger::set_filter(&config.runtime.log_level);
 
-    if let Some(store_path) = &store_path {
+    if let Some(store_path) = &config.store_path {
         info!("Using persistent store at {}", store_path.display());
     } else {
         info!("Using in-memory store");
     }
 
-    match WsState::new(store_path).map(Arc::new) {
+    // cancelled on SIGTERM or SIGINT
+    let shutdown = CancellationToken::new();
+    {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            match shutdown_signal().await {
+                Ok(()) => {
+                    info!("Shutting down");
+                    shutdown.cancel();
+                },
+                Err(err) => error!("Could not listen for shutdown signals: {err}")
+            }
+        });
+    }
+
+    match WsState::new(&config, shutdown.clone()).map(Arc::new) {
         Ok(ws_state) => {
+            tokio::spawn(ws_state.run_webhooks());
+            tokio::spawn(ws_state.run_reminders());
+            tokio::spawn(reload_on_hangup(args, ws_state.clone()));
+
             // serve
-            match args().try_into() {
-                Ok(addr) => {
-                    let state = FullState {
-                        ws_state,
-                        allowed_origins
-                    };
-                    
-                    let app = Router::new()
-                        .route("/socket", get(socket))
-                        .with_state(state)
-                        .nest_service("/", ServeDir::new("static"));
-                    
-                    if let Err(err) = serve(app, addr).await {
-                        error!("An error occurred while serving the application: {err}");
-                    }
-                }
-                Err(err) => {
-                    error!("Invalid bind address: {err}");
-                }
+            let state = FullState {
+                ws_state: ws_state.clone()
+            };
+
+            let static_files = match config.static_files {
+                StaticFiles::Dir(static_dir) => Router::new().fallback_service(ServeDir::new(static_dir)),
+                #[cfg(feature = "embed-static")]
+                StaticFiles::Embedded => Router::new().fallback(embedded::serve),
+            };
+
+            // posts to incoming webhooks are held to the same size limit as websocket messages
+            let max_webhook_size = config.limits.max_websocket_message_size;
+            let app = Router::new()
+                .route("/socket", get(socket))
+                .route("/avatar/:id", get(avatar))
+                .route("/hooks/:token", post(incoming_webhook).layer(DefaultBodyLimit::max(max_webhook_size)))
+                .with_state(state)
+                .merge(static_files.layer(middleware::from_fn(headers::static_cache_control)));
+            let app = headers::harden(app, config.content_security_policy.as_deref(), config.tls.is_some());
+
+            if let Err(err) = serve(app, config.bind, config.tls, shutdown, SHUTDOWN_TIMEOUT).await {
+                error!("An error occurred while serving the application: {err}");
             }
+            ws_state.shutdown(SHUTDOWN_TIMEOUT).await;
         },
         Err(err) => {
             error!("Error while in store init: {err}");
@@ -65,19 +107,91 @@ async fn main() {
     }
 }
 
-async fn socket(ws: WebSocketUpgrade, headers: HeaderMap, State(state): State<FullState>) -> impl IntoResponse {
+/// re-read the config on SIGHUP and apply the settings that can change while running
+async fn reload_on_hangup(args: Args, ws_state: Arc<WsState>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => return error!("Could not listen for SIGHUP: {err}")
+    };
+    while hangup.recv().await.is_some() {
+        match Config::load(&args) {
+            Ok(config) => {
+                logger::set_filter(&config.runtime.log_level);
+                ws_state.reload(config.runtime);
+                info!("Reloaded origins, admins, rate limit and log level");
+            },
+            // keep going with what we had
+            Err(err) => error!("Could not reload config: {err}")
+        }
+    }
+}
+
+async fn socket(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    remote_addr: Option<Extension<RemoteAddr>>,
+    State(state): State<FullState>
+) -> impl IntoResponse {
+    let mut remote_addr = remote_addr
+        .map(|Extension(RemoteAddr(addr))| addr)
+        .unwrap_or_else(|| "unknown".into());
+    // behind a reverse proxy, the peer is just the proxy
+    if let Some(forwarded) = headers.get("x-forwarded-for").and_then(|f| f.to_str().ok()) {
+        remote_addr = format!("{remote_addr} (forwarded for {forwarded})");
+    }
+
     // websockets are not subject to CORS
-    let origin = headers.get(ORIGIN);
-    if origin
-        .and_then(|o| o.to_str().ok())
-        .is_some_and(|a| state.allowed_origins.iter().any(|o| o == a)) {
-        // actually handle this websocket
-        ws.on_upgrade(move |socket| async {
-            let mut handler = WsHandler::new(socket, state.ws_state);
-            handler.handle().await;
-        }).into_response()
-    } else {
-        // not an allowed origin
-        StatusCode::FORBIDDEN.into_response()
+    let origin = headers.get(ORIGIN).and_then(|o| o.to_str().ok());
+    let host = headers.get(HOST).and_then(|h| h.to_str().ok());
+    if let Err(reason) = state.ws_state.runtime().allowed_origins.check(origin, host) {
+        warn!(
+            "Rejected websocket from {remote_addr} with Origin {} for Host {}: {reason}",
+            origin.unwrap_or("(none)"),
+            host.unwrap_or("(none)")
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // actually handle this websocket
+    let max_size = state.ws_state.limits.max_websocket_message_size;
+    ws.max_message_size(max_size).max_frame_size(max_size).on_upgrade(move |socket| state.ws_state.clone().track(async {
+        let mut handler = WsHandler::new(socket, state.ws_state, remote_addr);
+        handler.handle().await;
+    })).into_response()
+}
+
+/// body of a request to an incoming webhook
+#[derive(Deserialize)]
+struct IncomingWebhookBody {
+    text: String
+}
+
+async fn incoming_webhook(
+    Path(token): Path<String>,
+    State(state): State<FullState>,
+    Json(body): Json<IncomingWebhookBody>
+) -> impl IntoResponse {
+    if body.text.trim().is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+    state.ws_state.post_incoming_webhook(token, body.text).await
+}
+
+async fn avatar(Path(user_id): Path<u16>, State(state): State<FullState>) -> impl IntoResponse {
+    let ws_state = state.ws_state.clone();
+    match spawn_blocking(move || ws_state.get_avatar(user_id)).await {
+        Ok(Ok(Some((content_type, data)))) => {
+            // clients bust the cache using the avatar's update time
+            ([(CONTENT_TYPE, content_type), (CACHE_CONTROL, "public, max-age=86400".to_owned())], data).into_response()
+        },
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(err)) => {
+            error!("Error while loading avatar: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        },
+        Err(err) => {
+            error!("Error while loading avatar: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
     }
 }
@@ -0,0 +1,192 @@
+//! Checking the Origin of websocket requests, as CORS does not apply to websockets
+
+use std::{fmt::Display, str::FromStr};
+
+use reqwest::Url;
+
+/// the pattern for "the same host the request was sent to"
+const SAME_HOST: &str = "same-host";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PortPattern {
+    Any,
+    /// `None` is the default port for the scheme
+    Exact(Option<u16>),
+}
+
+/// one entry of `allowed_origins`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OriginPattern {
+    /// an origin exactly as browsers send it
+    Exact(String),
+    /// `*` can stand for the scheme, the port, or a host label. a leading `*` label also covers
+    /// deeper subdomains
+    Wildcard { scheme: Option<String>, host: Vec<String>, port: PortPattern },
+    /// the Origin has the same host and port as the Host header
+    SameHost,
+}
+
+impl FromStr for OriginPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{pattern} is not an origin like https://example.com, a pattern like https://*.example.com, or {SAME_HOST}");
+
+        if pattern == SAME_HOST {
+            return Ok(Self::SameHost);
+        }
+
+        if !pattern.contains('*') {
+            let url = Url::parse(pattern).map_err(|_| invalid())?;
+            if !matches!(url.scheme(), "http" | "https") || url.origin().ascii_serialization() != pattern {
+                return Err(invalid());
+            }
+            return Ok(Self::Exact(pattern.to_owned()));
+        }
+
+        let (scheme, rest) = pattern.split_once("://").ok_or_else(invalid)?;
+        let scheme = match scheme {
+            "*" => None,
+            "http" | "https" => Some(scheme.to_owned()),
+            _ => return Err(invalid())
+        };
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, "*")) => (host, PortPattern::Any),
+            Some((host, port)) => (host, PortPattern::Exact(Some(port.parse().map_err(|_| invalid())?))),
+            None => (rest, PortPattern::Exact(None))
+        };
+        let host: Vec<String> = host.split('.').map(str::to_ascii_lowercase).collect();
+        let valid_label = |label: &String| label == "*"
+            || (!label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+        if !host.iter().all(valid_label) {
+            return Err(invalid());
+        }
+        Ok(Self::Wildcard { scheme, host, port })
+    }
+}
+
+/// whether every label matches, `*` matching any one label
+fn labels_match(pattern: &[String], labels: &[&str]) -> bool {
+    pattern.len() == labels.len()
+        && pattern.iter().zip(labels).all(|(pattern, label)| pattern == "*" || pattern.eq_ignore_ascii_case(label))
+}
+
+impl OriginPattern {
+    fn matches(&self, origin: &str, url: &Url, request_host: Option<&str>) -> bool {
+        match self {
+            Self::Exact(allowed) => allowed == origin,
+            Self::Wildcard { scheme, host, port } => {
+                let scheme_matches = scheme.is_none() || scheme.as_deref() == Some(url.scheme());
+                let port_matches = match port {
+                    PortPattern::Any => true,
+                    PortPattern::Exact(None) => url.port().is_none(),
+                    PortPattern::Exact(port) => url.port_or_known_default() == *port
+                };
+                let labels: Vec<&str> = url.host_str().unwrap_or_default().split('.').collect();
+                let host_matches = match host.split_first() {
+                    // a leading `*` covers any number of subdomains
+                    Some((first, rest)) if first == "*" => {
+                        labels.len() > rest.len() && labels_match(rest, &labels[labels.len() - rest.len()..])
+                    },
+                    _ => labels_match(host, &labels)
+                };
+                scheme_matches && port_matches && host_matches
+            },
+            Self::SameHost => {
+                let authority = match (url.host_str(), url.port()) {
+                    (Some(host), Some(port)) => format!("{host}:{port}"),
+                    (Some(host), None) => host.to_owned(),
+                    (None, _) => return false
+                };
+                request_host.is_some_and(|request_host| request_host.eq_ignore_ascii_case(&authority))
+            }
+        }
+    }
+}
+
+/// why a websocket was turned away
+#[derive(Debug, PartialEq, Eq)]
+pub enum OriginRejected {
+    Missing,
+    Invalid,
+    NotAllowed,
+}
+
+impl Display for OriginRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "there is no Origin header"),
+            Self::Invalid => write!(f, "the Origin header is not an http(s) origin"),
+            Self::NotAllowed => write!(f, "the origin does not match any of allowed_origins"),
+        }
+    }
+}
+
+/// the parsed `allowed_origins`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedOrigins(Vec<OriginPattern>);
+
+impl AllowedOrigins {
+    pub fn parse<T: AsRef<str>>(patterns: &[T]) -> Result<Self, String> {
+        patterns.iter().map(|pattern| pattern.as_ref().parse()).collect::<Result<_, _>>().map(Self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `request_host` is the Host header, for `same-host`
+    pub fn check(&self, origin: Option<&str>, request_host: Option<&str>) -> Result<(), OriginRejected> {
+        let origin = origin.ok_or(OriginRejected::Missing)?;
+        let url = Url::parse(origin)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or(OriginRejected::Invalid)?;
+        if self.0.iter().any(|pattern| pattern.matches(origin, &url, request_host)) {
+            Ok(())
+        } else {
+            Err(OriginRejected::NotAllowed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AllowedOrigins, OriginRejected};
+
+    #[test]
+    fn origin_patterns() {
+        let allowed = AllowedOrigins::parse(&[
+            "https://chat.example.com",
+            "https://*.preview.example.com",
+            "http://192.168.1.*:*",
+            "*://localhost:8080",
+        ]).unwrap();
+        let check = |origin| allowed.check(Some(origin), None);
+
+        assert_eq!(check("https://chat.example.com"), Ok(()));
+        assert_eq!(check("https://pr-12.preview.example.com"), Ok(()));
+        assert_eq!(check("https://a.b.preview.example.com"), Ok(()));
+        assert_eq!(check("https://preview.example.com"), Err(OriginRejected::NotAllowed));
+        assert_eq!(check("http://pr-12.preview.example.com"), Err(OriginRejected::NotAllowed));
+        assert_eq!(check("http://192.168.1.20:3000"), Ok(()));
+        assert_eq!(check("http://192.168.1.20"), Ok(()));
+        assert_eq!(check("http://192.168.2.20:3000"), Err(OriginRejected::NotAllowed));
+        assert_eq!(check("https://localhost:8080"), Ok(()));
+        assert_eq!(check("http://localhost:8081"), Err(OriginRejected::NotAllowed));
+        assert_eq!(check("null"), Err(OriginRejected::Invalid));
+        assert_eq!(allowed.check(None, None), Err(OriginRejected::Missing));
+
+        let same_host = AllowedOrigins::parse(&["same-host"]).unwrap();
+        assert_eq!(same_host.check(Some("http://10.0.0.5:8080"), Some("10.0.0.5:8080")), Ok(()));
+        assert_eq!(same_host.check(Some("https://Chat.example.com"), Some("chat.example.com")), Ok(()));
+        assert_eq!(same_host.check(Some("http://10.0.0.5:8080"), Some("10.0.0.5:8081")), Err(OriginRejected::NotAllowed));
+        assert_eq!(same_host.check(Some("http://10.0.0.5:8080"), None), Err(OriginRejected::NotAllowed));
+
+        assert!(AllowedOrigins::parse(&["https://example.com/"]).is_err());
+        assert!(AllowedOrigins::parse(&["ftp://*.example.com"]).is_err());
+        assert!(AllowedOrigins::parse(&["https://*.example.com/path"]).is_err());
+        assert!(AllowedOrigins::parse(&["https://*.example.com:port"]).is_err());
+        assert!(AllowedOrigins::parse(&["same-origin"]).is_err());
+    }
+}
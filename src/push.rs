@@ -0,0 +1,297 @@
+//! Web Push: payload encryption (RFC 8291) and VAPID authentication (RFC 8292)
+
+use std::{fmt::Display, time::Duration};
+
+use aes_gcm::{aead::Aead, Aes128Gcm, KeyInit};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hkdf::Hkdf;
+use p256::{
+    ecdh::EphemeralSecret,
+    ecdsa::{signature::Signer, Signature, SigningKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+    PublicKey,
+};
+use rand::{rngs::OsRng, RngCore};
+use reqwest::{header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE}, redirect::Policy, Client, StatusCode, Url};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::store::PushSubscription;
+
+/// how long push services should hold on to a message for an offline device
+const PUSH_TTL: u32 = 24 * 60 * 60;
+/// how long the signed VAPID tokens are valid for (the spec allows up to 24 hours)
+const VAPID_TOKEN_LIFETIME: i64 = 12 * 60 * 60;
+/// we always send a single record, so this just has to be bigger than the payload
+const RECORD_SIZE: u32 = 4096;
+
+#[derive(Debug)]
+pub enum PushError {
+    InvalidKey,
+    InvalidEndpoint,
+    Encryption,
+    Request(reqwest::Error),
+    /// the subscription expired or was revoked, so it should be forgotten
+    Gone,
+    Status(StatusCode),
+}
+
+impl From<reqwest::Error> for PushError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Request(value)
+    }
+}
+
+impl Display for PushError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidKey => write!(f, "Invalid push key"),
+            Self::InvalidEndpoint => write!(f, "Invalid push endpoint"),
+            Self::Encryption => write!(f, "Could not encrypt push message"),
+            Self::Request(err) => write!(f, "Push request failed: {err}"),
+            Self::Gone => write!(f, "Push subscription is no longer valid"),
+            Self::Status(status) => write!(f, "Push service responded with {status}"),
+        }
+    }
+}
+impl std::error::Error for PushError {}
+
+/// the application server's key pair, which browsers use to verify pushes came from us
+pub struct VapidKey(SigningKey);
+
+impl VapidKey {
+    pub fn generate() -> Self {
+        Self(SigningKey::random(&mut OsRng))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PushError> {
+        SigningKey::from_slice(bytes)
+            .map(Self)
+            .map_err(|_| PushError::InvalidKey)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_bytes().to_vec()
+    }
+
+    /// the public key in the format `PushManager.subscribe` expects for `applicationServerKey`
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.0.verifying_key().to_encoded_point(false))
+    }
+
+    /// build the `Authorization` header for a push to `endpoint`
+    fn authorization(&self, endpoint: &Url, subject: &str) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({ "typ": "JWT", "alg": "ES256" }).to_string());
+        let claims = URL_SAFE_NO_PAD.encode(json!({
+            "aud": endpoint.origin().ascii_serialization(),
+            "exp": Utc::now().timestamp() + VAPID_TOKEN_LIFETIME,
+            "sub": subject
+        }).to_string());
+
+        let unsigned = format!("{header}.{claims}");
+        let signature: Signature = self.0.sign(unsigned.as_bytes());
+        let token = format!("{unsigned}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+
+        format!("vapid t={token}, k={}", self.public_key())
+    }
+}
+
+fn hkdf_expand<const N: usize>(salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; N] {
+    let mut okm = [0; N];
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, &mut okm)
+        .expect("output is short enough");
+    okm
+}
+
+/// encrypt a payload for a subscription with the `aes128gcm` content encoding
+fn encrypt(payload: &[u8], subscription: &PushSubscription) -> Result<Vec<u8>, PushError> {
+    let ua_public = URL_SAFE_NO_PAD.decode(&subscription.p256dh).map_err(|_| PushError::InvalidKey)?;
+    let auth_secret = URL_SAFE_NO_PAD.decode(&subscription.auth).map_err(|_| PushError::InvalidKey)?;
+    let ua_key = PublicKey::from_sec1_bytes(&ua_public).map_err(|_| PushError::InvalidKey)?;
+
+    // a fresh key pair for every message
+    let as_secret = EphemeralSecret::random(&mut OsRng);
+    let as_public = as_secret.public_key().to_encoded_point(false);
+    let shared_secret = as_secret.diffie_hellman(&ua_key);
+
+    let mut salt = [0; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    // combine the shared secret with the subscription's auth secret
+    let key_info = [b"WebPush: info\0", ua_public.as_slice(), as_public.as_bytes()].concat();
+    let ikm: [u8; 32] = hkdf_expand(&auth_secret, shared_secret.raw_secret_bytes(), &key_info);
+
+    let cek: [u8; 16] = hkdf_expand(&salt, &ikm, b"Content-Encoding: aes128gcm\0");
+    let nonce: [u8; 12] = hkdf_expand(&salt, &ikm, b"Content-Encoding: nonce\0");
+
+    // 0x02 marks the last (and only) record, with no padding
+    let plaintext = [payload, &[2]].concat();
+    let ciphertext = Aes128Gcm::new(&cek.into())
+        .encrypt(&nonce.into(), plaintext.as_slice())
+        .map_err(|_| PushError::Encryption)?;
+
+    // header: salt, record size, key id (our public key), then the single record
+    let mut body = Vec::with_capacity(16 + 4 + 1 + 65 + ciphertext.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(as_public.as_bytes());
+    body.extend_from_slice(&ciphertext);
+    Ok(body)
+}
+
+/// sends encrypted push messages to browsers' push services
+pub struct PushSender {
+    client: Client,
+    key: VapidKey,
+    /// a `mailto:` or `https:` contact for push service operators
+    subject: String,
+}
+
+impl PushSender {
+    pub fn new(key: VapidKey, subject: String) -> Self {
+        // a redirect could point the request at an internal address that was never checked
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .redirect(Policy::none())
+            .build()
+            .expect("TLS backend is available");
+        Self { client, key, subject }
+    }
+
+    pub fn public_key(&self) -> String {
+        self.key.public_key()
+    }
+
+    pub async fn send(&self, subscription: &PushSubscription, payload: &[u8]) -> Result<(), PushError> {
+        let endpoint: Url = subscription.endpoint.parse().map_err(|_| PushError::InvalidEndpoint)?;
+        let body = encrypt(payload, subscription)?;
+
+        let response = self.client.post(endpoint.clone())
+            .header(AUTHORIZATION, self.key.authorization(&endpoint, &self.subject))
+            .header(CONTENT_ENCODING, "aes128gcm")
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .header("TTL", PUSH_TTL)
+            .body(body)
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND | StatusCode::GONE => Err(PushError::Gone),
+            status => Err(PushError::Status(status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use aes_gcm::{aead::Aead, Aes128Gcm, KeyInit};
+    use axum::{body::Bytes, extract::State, http::{HeaderMap, StatusCode}, routing::post, Router};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use p256::{
+        ecdsa::{signature::Verifier, Signature, VerifyingKey},
+        elliptic_curve::sec1::ToEncodedPoint,
+        PublicKey, SecretKey,
+    };
+    use rand::rngs::OsRng;
+    use tokio::net::TcpListener;
+
+    use super::{hkdf_expand, PushError, PushSender, VapidKey};
+    use crate::store::PushSubscription;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    /// a push service that records every push it gets
+    async fn mock_push_service() -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route("/push/ok", post(|State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                received.lock().unwrap().push((headers, body));
+                StatusCode::CREATED
+            }))
+            .route("/push/gone", post(|| async { StatusCode::GONE }))
+            .with_state(received.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{addr}"), received)
+    }
+
+    /// what the browser does with a push it receives
+    fn decrypt(body: &[u8], ua_secret: &SecretKey, auth_secret: &[u8]) -> Vec<u8> {
+        let (salt, rest) = body.split_at(16);
+        let (_record_size, rest) = rest.split_at(4);
+        let (key_id_len, rest) = rest.split_at(1);
+        let (as_public, ciphertext) = rest.split_at(key_id_len[0] as usize);
+
+        let as_key = PublicKey::from_sec1_bytes(as_public).unwrap();
+        let shared_secret = p256::ecdh::diffie_hellman(ua_secret.to_nonzero_scalar(), as_key.as_affine());
+        let ua_public = ua_secret.public_key().to_encoded_point(false);
+
+        let key_info = [b"WebPush: info\0", ua_public.as_bytes(), as_public].concat();
+        let ikm: [u8; 32] = hkdf_expand(auth_secret, shared_secret.raw_secret_bytes(), &key_info);
+        let cek: [u8; 16] = hkdf_expand(salt, &ikm, b"Content-Encoding: aes128gcm\0");
+        let nonce: [u8; 12] = hkdf_expand(salt, &ikm, b"Content-Encoding: nonce\0");
+
+        let mut plaintext = Aes128Gcm::new(&cek.into())
+            .decrypt(&nonce.into(), ciphertext)
+            .unwrap();
+        // strip the delimiter
+        assert_eq!(plaintext.pop(), Some(2));
+        plaintext
+    }
+
+    #[tokio::test]
+    async fn send_push() {
+        let (base, received) = mock_push_service().await;
+
+        let ua_secret = SecretKey::random(&mut OsRng);
+        let auth_secret = [7; 16];
+        let subscription = PushSubscription {
+            endpoint: format!("{base}/push/ok"),
+            p256dh: URL_SAFE_NO_PAD.encode(ua_secret.public_key().to_encoded_point(false)),
+            auth: URL_SAFE_NO_PAD.encode(auth_secret)
+        };
+
+        let key = VapidKey::generate();
+        // keys survive a round trip through the store
+        let key = VapidKey::from_bytes(&key.to_bytes()).unwrap();
+        let public_key = key.public_key();
+        let sender = PushSender::new(key, "mailto:admin@example.com".into());
+
+        sender.send(&subscription, b"hello").await.unwrap();
+
+        let (headers, body) = received.lock().unwrap().pop().unwrap();
+        assert_eq!(headers["content-encoding"], "aes128gcm");
+        assert!(headers.contains_key("ttl"));
+        assert_eq!(decrypt(&body, &ua_secret, &auth_secret), b"hello");
+
+        // check the VAPID token was signed by our key
+        let authorization = headers["authorization"].to_str().unwrap();
+        let (token, k) = authorization
+            .strip_prefix("vapid t=").unwrap()
+            .split_once(", k=").unwrap();
+        assert_eq!(k, public_key);
+
+        let (unsigned, signature) = token.rsplit_once('.').unwrap();
+        let verifying_key = VerifyingKey::from_sec1_bytes(&URL_SAFE_NO_PAD.decode(k).unwrap()).unwrap();
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+        verifying_key.verify(unsigned.as_bytes(), &signature).unwrap();
+
+        let claims = unsigned.split_once('.').unwrap().1;
+        let claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["aud"], base);
+        assert_eq!(claims["sub"], "mailto:admin@example.com");
+
+        // expired subscriptions are reported so they can be removed
+        let gone = PushSubscription { endpoint: format!("{base}/push/gone"), ..subscription };
+        assert!(matches!(sender.send(&gone, b"hello").await, Err(PushError::Gone)));
+    }
+}
@@ -1,9 +1,11 @@
 use std::{collections::{HashMap, HashSet}, fmt::Display, fs::OpenOptions, os::unix::fs::OpenOptionsExt, path::Path};
 
 use chrono::Utc;
+use log::info;
+use rand::{distributions::{Alphanumeric, DistString}, thread_rng};
 use redb::{
-    backends::InMemoryBackend,  Database, Key, ReadableTable, TableDefinition,
-    TypeName, Value,
+    backends::InMemoryBackend,  Database, Key, ReadableTable, ReadableTableMetadata, TableDefinition,
+    TypeName, Value, WriteTransaction,
 };
 use serde::{Deserialize, Serialize};
 
@@ -45,12 +47,274 @@ pub struct Message {
     pub recipient: MessageRecipient,
     pub message: String,
     pub time: i64,
-    pub tags: Vec<String>
+    pub tags: Vec<String>,
+    /// set if this message was forwarded from another conversation
+    #[serde(default)]
+    pub forwarded: Option<ForwardedFrom>
+}
+
+/// name shown for users that have been deleted
+///
+/// this can't be requested as a username, so nobody can impersonate a deleted user
+const DELETED_USERNAME: &str = "[deleted]";
+
+/// account state that isn't part of the username tables
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct UserDetails {
+    /// deactivated users can't log in
+    #[serde(default)]
+    pub deactivated: bool,
+    /// deleted users only exist so their old messages have a sender
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    /// when the avatar was last changed, if they have one
+    #[serde(default)]
+    pub avatar_updated: Option<i64>,
+    /// server admins can moderate the whole instance
+    #[serde(default)]
+    pub admin: bool,
+    /// bots post through webhooks instead of logging in
+    #[serde(default)]
+    pub bot: bool
+}
+
+/// what a member is allowed to do in a group
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum GroupRole {
+    Member,
+    /// can change the membership and delete the group
+    Admin,
+    /// can also promote/demote other members
+    Owner
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Group {
+    pub name: String,
+    pub members: HashMap<u16, GroupRole>,
+    /// public channels can be listed and joined by anyone
+    #[serde(default)]
+    pub public: bool
+}
+
+impl Group {
+    pub fn is_member(&self, user_id: u16) -> bool {
+        self.members.contains_key(&user_id)
+    }
+
+    /// the role of this user, or `PermissionDenied` if they aren't a member
+    fn role_of(&self, user_id: u16) -> Result<GroupRole> {
+        self.members.get(&user_id).copied().ok_or(StoreError::PermissionDenied)
+    }
+
+    /// make sure this user has at least the given role
+    fn require_role(&self, user_id: u16, role: GroupRole) -> Result<GroupRole> {
+        let user_role = self.role_of(user_id)?;
+        if user_role < role {
+            return Err(StoreError::InsufficientRole);
+        }
+        Ok(user_role)
+    }
+
+    fn has_owner(&self) -> bool {
+        self.members.values().any(|role| *role == GroupRole::Owner)
+    }
+
+    /// add new users as regular members, returning the ones that weren't already in the group
+    fn add_members(&mut self, users: impl IntoIterator<Item = u16>) -> Vec<u16> {
+        users.into_iter()
+            .filter(|user| {
+                if self.is_member(*user) { return false; }
+                self.members.insert(*user, GroupRole::Member);
+                true
+            })
+            .collect()
+    }
+
+    /// remove members on behalf of `user_id`, returning the ones that were actually in the group
+    fn remove_members(&mut self, users: impl IntoIterator<Item = u16>, user_id: u16) -> Result<Vec<u16>> {
+        // only admins can change the membership
+        let role = self.require_role(user_id, GroupRole::Admin)?;
+
+        let mut removed = vec![];
+        for user in users {
+            if let Some(&removed_role) = self.members.get(&user) {
+                // only owners can remove other admins/owners
+                if removed_role > GroupRole::Member && role < GroupRole::Owner {
+                    return Err(StoreError::InsufficientRole);
+                }
+                self.members.remove(&user);
+                removed.push(user);
+            }
+        }
+
+        if !self.has_owner() {
+            return Err(StoreError::LastOwner);
+        }
+        Ok(removed)
+    }
+
+    /// promote the most senior remaining member if nobody owns this group anymore
+    fn ensure_owner(&mut self) {
+        if self.has_owner() { return; }
+        // highest role first, then lowest id
+        if let Some((_, role)) = self.members.iter_mut().max_by_key(|(id, role)| (**role, std::cmp::Reverse(**id))) {
+            *role = GroupRole::Owner;
+        }
+    }
+}
+
+/// an invite link that lets anyone join a group
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct GroupInvite {
+    pub group: u16,
+    pub creator: u16,
+    /// unix timestamp after which the invite can't be used
+    pub expires: Option<i64>,
+    /// how many more times the invite can be used
+    pub uses_left: Option<u32>
+}
+
+impl GroupInvite {
+    /// whether this invite can still be used at time `now`
+    fn is_valid(&self, now: i64) -> bool {
+        let expired = matches!(self.expires, Some(expires) if expires <= now);
+        !expired && self.uses_left != Some(0)
+    }
+}
+
+/// the original author of a forwarded message
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct ForwardedFrom {
+    pub sender: u16,
+    pub time: i64
+}
+
+/// a browser's Web Push subscription. the keys are base64url encoded
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct PushSubscription {
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String
+}
+
+/// lets external services post into a conversation as a bot user
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct IncomingWebhook {
+    pub bot: u16,
+    pub recipient: MessageRecipient,
+    pub creator: u16
+}
+
+/// a bot account that logs in with an API token instead of a username
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Bot {
+    pub owner: u16,
+    pub token: String
+}
+
+/// which messages an outgoing webhook is notified about
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum WebhookTrigger {
+    Conversation(MessageRecipient),
+    /// messages with this tag in any conversation the owner can read
+    Tag(String)
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Webhook {
+    pub owner: u16,
+    pub url: String,
+    /// used to sign deliveries so the receiver can verify them
+    pub secret: String,
+    pub trigger: WebhookTrigger,
+    /// tag webhooks created by server admins see every conversation
+    pub global: bool
+}
+
+impl Webhook {
+    /// whether this webhook should be notified about `message`
+    fn matches(&self, tx: &WriteTransaction, message: &Message) -> Result<bool> {
+        match &self.trigger {
+            WebhookTrigger::Conversation(MessageRecipient::Group(group_id)) => {
+                Ok(message.recipient == MessageRecipient::Group(*group_id)
+                    && (self.global || is_participant(tx, message, self.owner)?))
+            },
+            // direct conversations are keyed by the other user
+            WebhookTrigger::Conversation(MessageRecipient::User(other)) => {
+                Ok((message.sender, message.recipient) == (self.owner, MessageRecipient::User(*other))
+                    || (message.sender, message.recipient) == (*other, MessageRecipient::User(self.owner)))
+            },
+            WebhookTrigger::Tag(tag) => {
+                Ok(message.tags.contains(tag) && (self.global || is_participant(tx, message, self.owner)?))
+            }
+        }
+    }
+}
+
+/// a pending webhook delivery
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct WebhookDelivery {
+    pub webhook: u16,
+    pub event: String,
+    /// the JSON body
+    pub payload: String,
+    pub attempts: u32,
+    pub next_attempt: i64
+}
+
+/// "remind me about this message at `time`"
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Reminder {
+    pub user: u16,
+    pub message: u16,
+    pub time: i64
+}
+
+/// overall counts, for server admins
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+pub struct StoreStats {
+    pub users: u64,
+    pub groups: u64,
+    pub messages: u64,
+    pub invites: u64,
+    pub avatars: u64
+}
+
+/// a security-relevant event in the audit log
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum AuditEvent {
+    Login { user: u16, remote_addr: String },
+    UserCreated { user: u16 },
+    GroupCreated { group: u16, by: u16 },
+    GroupEdited { group: u16, by: u16, change: String },
+    GroupDeleted { group: u16, by: u16 },
+    /// only recorded when somebody other than the sender deletes a message
+    MessageDeleted { message: u16, sender: u16, by: u16 },
+    AdminAction { admin: u16, action: String },
+    /// message contents get sent to the URL, so this is worth knowing about
+    WebhookCreated { webhook: u16, by: u16, url: String }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+pub struct AuditEntry {
+    pub time: i64,
+    pub event: AuditEvent
 }
 
 const USERS_TABLE: TableDefinition<u16, String> = TableDefinition::new("users");
 const USERS_TABLE_REVERSE: TableDefinition<&str, u16> = TableDefinition::new("users_reverse");
-const GROUPS_TABLE: TableDefinition<u16, (String, MsgPackRedb<HashSet<u16>, 'H'>)> = TableDefinition::new("groups");
+const USER_DETAILS_TABLE: TableDefinition<u16, MsgPackRedb<UserDetails, 'U'>> = TableDefinition::new("user_details");
+// (content type, image data)
+const AVATARS_TABLE: TableDefinition<u16, (&str, &[u8])> = TableDefinition::new("avatars");
+const GROUPS_TABLE: TableDefinition<u16, MsgPackRedb<Group, 'G'>> = TableDefinition::new("groups");
+/// groups table before roles were added - migrated on init
+const LEGACY_GROUPS_TABLE: TableDefinition<u16, (String, MsgPackRedb<HashSet<u16>, 'H'>)> = TableDefinition::new("groups");
+const GROUP_INVITES_TABLE: TableDefinition<&str, MsgPackRedb<GroupInvite, 'I'>> = TableDefinition::new("group_invites");
 const MESSAGES_TABLE: TableDefinition<
     u16,
     MsgPackRedb<Message, 'M'>,
@@ -58,15 +322,46 @@ const MESSAGES_TABLE: TableDefinition<
 // (recipient, sender, message id)
 const MSG_ENDPOINT_TABLE: TableDefinition<(MsgPackRedb<MessageRecipient, 'R'>, u16, u16), ()> =
     TableDefinition::new("message_senders");
+// (blocker, blocked user)
+const BLOCKS_TABLE: TableDefinition<(u16, u16), ()> = TableDefinition::new("blocks");
+// (conversation, user that muted it). direct conversations are keyed by the other user
+const MUTES_TABLE: TableDefinition<(MsgPackRedb<MessageRecipient, 'R'>, u16), ()> = TableDefinition::new("mutes");
+// (user that muted it, conversation)
+const MUTES_TABLE_REVERSE: TableDefinition<(u16, MsgPackRedb<MessageRecipient, 'R'>), ()> = TableDefinition::new("mutes_reverse");
+// (user, endpoint) -> (p256dh, auth)
+const PUSH_SUBSCRIPTIONS_TABLE: TableDefinition<(u16, &str), (&str, &str)> = TableDefinition::new("push_subscriptions");
+const VAPID_KEY_TABLE: TableDefinition<(), &[u8]> = TableDefinition::new("vapid_key");
+// token -> webhook
+const INCOMING_WEBHOOKS_TABLE: TableDefinition<&str, MsgPackRedb<IncomingWebhook, 'K'>> = TableDefinition::new("incoming_webhooks");
+const REMINDERS_TABLE: TableDefinition<u64, MsgPackRedb<Reminder, 'E'>> = TableDefinition::new("reminders");
+/// reminders table from when it shared its type name with messages - migrated on init
+const LEGACY_REMINDERS_TABLE: TableDefinition<u64, MsgPackRedb<Reminder, 'M'>> = TableDefinition::new("reminders");
+const BOTS_TABLE: TableDefinition<u16, MsgPackRedb<Bot, 'O'>> = TableDefinition::new("bots");
+const BOT_TOKENS_TABLE: TableDefinition<&str, u16> = TableDefinition::new("bot_tokens");
+const WEBHOOKS_TABLE: TableDefinition<u16, MsgPackRedb<Webhook, 'W'>> = TableDefinition::new("webhooks");
+const WEBHOOK_QUEUE_TABLE: TableDefinition<u64, MsgPackRedb<WebhookDelivery, 'D'>> = TableDefinition::new("webhook_queue");
+// (time, sequence number within that second)
+const AUDIT_TABLE: TableDefinition<(i64, u32), MsgPackRedb<AuditEvent, 'A'>> = TableDefinition::new("audit");
 
 #[derive(Debug)]
 pub enum StoreError {
-    RedbError(redb::Error),
+    RedbError(Box<redb::Error>),
     InvalidUserIds,
     InvalidGroupId,
     InvalidMessageId,
+    InvalidWebhookId,
+    InvalidBotToken,
+    InvalidReminderId,
     UsernameInUse,
-    PermissionDenied
+    PermissionDenied,
+    /// the user is a member but their group role doesn't allow this
+    InsufficientRole,
+    LastOwner,
+    InvalidInvite,
+    /// an invite that could never be redeemed
+    InvalidMaxUses,
+    /// the recipient has blocked the sender
+    Blocked
 }
 
 impl<T> From<T> for StoreError
@@ -74,7 +369,7 @@ where
     T: Into<redb::Error>,
 {
     fn from(value: T) -> Self {
-        Self::RedbError(value.into())
+        Self::RedbError(Box::new(value.into()))
     }
 }
 impl Display for StoreError {
@@ -84,8 +379,16 @@ impl Display for StoreError {
             StoreError::InvalidUserIds => write!(f, "Invalid user ID(s)"),
             StoreError::InvalidGroupId => write!(f, "Invalid group ID"),
             StoreError::InvalidMessageId => write!(f, "Invalid message ID"),
+            StoreError::InvalidWebhookId => write!(f, "Invalid webhook ID"),
+            StoreError::InvalidBotToken => write!(f, "Invalid bot token"),
+            StoreError::InvalidReminderId => write!(f, "Invalid reminder ID"),
             StoreError::PermissionDenied => write!(f, "Permission denied"),
-            StoreError::UsernameInUse => write!(f, "Username is already in use")
+            StoreError::UsernameInUse => write!(f, "Username is already in use"),
+            StoreError::InsufficientRole => write!(f, "Your role in this group does not allow that"),
+            StoreError::LastOwner => write!(f, "A group must have at least one owner"),
+            StoreError::InvalidInvite => write!(f, "Invalid or expired invite"),
+            StoreError::InvalidMaxUses => write!(f, "An invite must allow at least one use"),
+            StoreError::Blocked => write!(f, "You can't send messages to this user")
         }
     }
 }
@@ -102,159 +405,1719 @@ macro_rules! ignore_nonexistent_table {
             Err(e) => Err(e),
         }
     }
-}
-
-pub struct Store {
-    db: Database,
-}
+}
+
+/// make sure `sender` exists and is allowed to send messages to `recipient`
+fn check_can_send(tx: &WriteTransaction, sender: u16, recipient: MessageRecipient) -> Result<()> {
+    let users = tx.open_table(USERS_TABLE)?;
+
+    // make sure the sender exists
+    if users.get(sender)?.is_none() {
+        return Err(StoreError::InvalidUserIds);
+    }
+
+    // make sure the recepient exists
+    match recipient {
+        MessageRecipient::Group(group_id) => {
+            let groups = tx.open_table(GROUPS_TABLE)?;
+            if let Some(group) = groups.get(group_id)? {
+                // make sure they're a member of this group
+                if !group.value().is_member(sender) {
+                    return Err(StoreError::PermissionDenied);
+                }
+            } else {
+                return Err(StoreError::InvalidGroupId);
+            };
+        },
+        MessageRecipient::User(user_id) => {
+            if users.get(user_id)?.is_none() {
+                return Err(StoreError::InvalidUserIds);
+            };
+            // nobody can read messages sent to deleted users
+            let details = tx.open_table(USER_DETAILS_TABLE)?;
+            if details.get(user_id)?.is_some_and(|d| d.value().deleted) {
+                return Err(StoreError::InvalidUserIds);
+            }
+            // or users that have blocked the sender
+            let blocks = tx.open_table(BLOCKS_TABLE)?;
+            if blocks.get((user_id, sender))?.is_some() {
+                return Err(StoreError::Blocked);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// whether `user_id` sent or received `message`
+fn is_participant(tx: &WriteTransaction, message: &Message, user_id: u16) -> Result<bool> {
+    if message.sender == user_id {
+        return Ok(true);
+    }
+
+    // check if they're the recipient
+    Ok(match message.recipient {
+        MessageRecipient::User(recipient_user_id) => recipient_user_id == user_id,
+        MessageRecipient::Group(group_id) => {
+            let groups = tx.open_table(GROUPS_TABLE)?;
+            let group = groups.get(group_id)?;
+            if let Some(group) = group {
+                // make sure they're a member of this group
+                group.value().is_member(user_id)
+            } else {
+                // group doesn't exist
+                false
+            }
+        }
+    })
+}
+
+/// delete all messages ever received by this group
+fn delete_group_messages(tx: &WriteTransaction, group_id: u16) -> Result<()> {
+    let group = MessageRecipient::Group(group_id);
+
+    let mut msg_endpoints = tx.open_table(MSG_ENDPOINT_TABLE)?;
+    let mut messages = tx.open_table(MESSAGES_TABLE)?;
+
+    // iterate through all messages received by this group
+    let mut deleted = vec![];
+    let messages_sent_to_group = msg_endpoints.extract_from_if((group, u16::MIN, u16::MIN)..=(group, u16::MAX, u16::MAX), |_, _| true)?;
+    for message in messages_sent_to_group {
+        let (message, _) = message?;
+        let (_, _, message_id) = message.value();
+        // delete the message
+        messages.remove(message_id)?;
+        deleted.push(message_id);
+    }
+
+    delete_message_reminders(tx, &deleted)
+}
+
+/// delete the reminders about deleted messages. message ids get reused, so they'd go off for
+/// whatever message is sent next otherwise
+fn delete_message_reminders(tx: &WriteTransaction, message_ids: &[u16]) -> Result<()> {
+    if !message_ids.is_empty() {
+        let mut reminders = tx.open_table(REMINDERS_TABLE)?;
+        reminders.retain(|_, reminder| !message_ids.contains(&reminder.message))?;
+    }
+    Ok(())
+}
+
+/// delete all invites for this group
+fn delete_group_invites(tx: &WriteTransaction, group_id: u16) -> Result<()> {
+    let mut invites = tx.open_table(GROUP_INVITES_TABLE)?;
+    invites.retain(|_, invite| invite.group != group_id)?;
+    Ok(())
+}
+
+/// delete the webhooks that `stale` picks, along with their pending deliveries
+fn delete_webhooks(tx: &WriteTransaction, stale: impl Fn(&Webhook) -> bool) -> Result<()> {
+    let mut webhooks = tx.open_table(WEBHOOKS_TABLE)?;
+    let deleted = webhooks.extract_if(|_, webhook| stale(&webhook))?
+        .map(|v| Ok(v?.0.value()))
+        .collect::<Result<Vec<_>>>()?;
+    if !deleted.is_empty() {
+        let mut queue = tx.open_table(WEBHOOK_QUEUE_TABLE)?;
+        queue.retain(|_, delivery| !deleted.contains(&delivery.webhook))?;
+    }
+    Ok(())
+}
+
+/// delete the webhooks for a group: all of them if the group is gone (its id will be reused), or
+/// only the ones owned by `removed` members, who can't read it anymore
+fn delete_group_webhooks(tx: &WriteTransaction, group_id: u16, removed: Option<&[u16]>) -> Result<()> {
+    let trigger = WebhookTrigger::Conversation(MessageRecipient::Group(group_id));
+    delete_webhooks(tx, |webhook| webhook.trigger == trigger && match removed {
+        Some(removed) => !webhook.global && removed.contains(&webhook.owner),
+        None => true
+    })
+}
+
+/// delete a user, either erasing their direct messages or leaving them attributed to a deleted user
+///
+/// the user's id is never reused. they are removed from all of their groups, and the changed groups
+/// are returned (`None` if the group was deleted because nobody was left). `by` is whoever is doing it
+fn delete_user(tx: &WriteTransaction, user_id: u16, erase_messages: bool, by: u16) -> Result<Vec<(u16, Option<Group>)>> {
+    let mut changed_groups = vec![];
+    let mut owned_bots = vec![];
+    {
+        let mut users = tx.open_table(USERS_TABLE)?;
+        let mut users_reverse = tx.open_table(USERS_TABLE_REVERSE)?;
+        let mut details_table = tx.open_table(USER_DETAILS_TABLE)?;
+
+        let username = users.get(user_id)?
+            .ok_or(StoreError::InvalidUserIds)?
+            .value();
+        if details_table.get(user_id)?.is_some_and(|d| d.value().deleted) {
+            return Err(StoreError::InvalidUserIds);
+        }
+
+        // free up the username, but keep the id so old messages still have a sender
+        users_reverse.remove(&*username)?;
+        users.insert(user_id, DELETED_USERNAME.to_owned())?;
+        // none of their profile is kept
+        let details = UserDetails { deleted: true, deactivated: true, ..Default::default() };
+        details_table.insert(user_id, details)?;
+        tx.open_table(AVATARS_TABLE)?.remove(user_id)?;
+        // and nothing should be pushed to them
+        let mut subscriptions = tx.open_table(PUSH_SUBSCRIPTIONS_TABLE)?;
+        subscriptions.retain(|(user, _), _| user != user_id)?;
+        // or reminded about anything
+        tx.open_table(REMINDERS_TABLE)?.retain(|_, reminder| reminder.user != user_id)?;
+        // their webhooks stop, and so do the incoming ones they created (or that post as them)
+        delete_webhooks(tx, |webhook| webhook.owner == user_id)?;
+        tx.open_table(INCOMING_WEBHOOKS_TABLE)?
+            .retain(|_, webhook| webhook.creator != user_id && webhook.bot != user_id)?;
+        // and bots can't log in anymore, including the ones they own
+        let mut bots = tx.open_table(BOTS_TABLE)?;
+        let mut bot_tokens = tx.open_table(BOT_TOKENS_TABLE)?;
+        if let Some(bot) = bots.remove(user_id)? {
+            bot_tokens.remove(&*bot.value().token)?;
+        }
+        for bot in bots.extract_if(|_, bot| bot.owner == user_id)? {
+            let (bot_id, bot) = bot?;
+            let bot_id = bot_id.value();
+            bot_tokens.remove(&*bot.value().token)?;
+            let mut details = details_table.get(bot_id)?.map(|d| d.value()).unwrap_or_default();
+            details.deactivated = true;
+            details_table.insert(bot_id, details)?;
+            owned_bots.push(bot_id);
+        }
+
+        if erase_messages {
+            let mut msg_endpoints = tx.open_table(MSG_ENDPOINT_TABLE)?;
+            let mut messages = tx.open_table(MESSAGES_TABLE)?;
+
+            // every direct message they sent or received
+            let user = MessageRecipient::User(user_id);
+            let direct_messages = msg_endpoints.extract_if(|(recipient, sender, _), _| {
+                recipient == user || (sender == user_id && matches!(recipient, MessageRecipient::User(_)))
+            })?;
+            let mut deleted = vec![];
+            for message in direct_messages {
+                let (message, _) = message?;
+                let (_, _, message_id) = message.value();
+                messages.remove(message_id)?;
+                deleted.push(message_id);
+            }
+            delete_message_reminders(tx, &deleted)?;
+        }
+
+        // take them out of all of their groups
+        let mut groups = tx.open_table(GROUPS_TABLE)?;
+        let member_of = groups.iter()?
+            .filter_map(|v| {
+                let v = match v {
+                    Ok(v) => v,
+                    Err(e) => return Some(Err(e))
+                };
+                let group = v.1.value();
+                group.is_member(user_id).then(|| Ok((v.0.value(), group)))
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        for (group_id, mut group) in member_of {
+            group.members.remove(&user_id);
+            if group.members.is_empty() {
+                groups.remove(group_id)?;
+                changed_groups.push((group_id, None));
+            } else {
+                group.ensure_owner();
+                groups.insert(group_id, &group)?;
+                changed_groups.push((group_id, Some(group)));
+            }
+        }
+    }
+    for (group_id, group) in &changed_groups {
+        let group_id = *group_id;
+        if group.is_none() {
+            delete_group_messages(tx, group_id)?;
+            delete_group_invites(tx, group_id)?;
+            delete_group_webhooks(tx, group_id, None)?;
+            insert_audit_event(tx, AuditEvent::GroupDeleted { group: group_id, by })?;
+        } else {
+            let change = format!("removed [{user_id}] (account deleted)");
+            insert_audit_event(tx, AuditEvent::GroupEdited { group: group_id, by, change })?;
+        }
+    }
+    for bot_id in owned_bots {
+        let action = format!("deactivated user {bot_id} (owner deleted)");
+        insert_audit_event(tx, AuditEvent::AdminAction { admin: by, action })?;
+    }
+    Ok(changed_groups)
+}
+
+/// change a user's details. deleted users can't be changed
+fn update_user_details(tx: &WriteTransaction, user_id: u16, update: impl FnOnce(&mut UserDetails)) -> Result<()> {
+    let users = tx.open_table(USERS_TABLE)?;
+    if users.get(user_id)?.is_none() {
+        return Err(StoreError::InvalidUserIds);
+    }
+
+    let mut details_table = tx.open_table(USER_DETAILS_TABLE)?;
+    let mut details = details_table.get(user_id)?.map(|d| d.value()).unwrap_or_default();
+    if details.deleted {
+        return Err(StoreError::InvalidUserIds);
+    }
+    update(&mut details);
+    details_table.insert(user_id, details)?;
+    Ok(())
+}
+
+/// add a message and its endpoint entry, returning the new message id
+fn insert_message(tx: &WriteTransaction, message: &Message) -> Result<u16> {
+    let mut messages = tx.open_table(MESSAGES_TABLE)?;
+    // add one to last key
+    let id = messages.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
+    messages.insert(id, message)?;
+
+    // add it to the endpoints table
+    let mut msg_endpoints = tx.open_table(MSG_ENDPOINT_TABLE)?;
+    msg_endpoints.insert((message.recipient, message.sender, id), ())?;
+
+    Ok(id)
+}
+
+/// append an event to the audit log, as part of the change it records. entries are never modified or
+/// removed
+fn insert_audit_event(tx: &WriteTransaction, event: AuditEvent) -> Result<()> {
+    let mut audit = tx.open_table(AUDIT_TABLE)?;
+    let time = Utc::now().timestamp();
+    // several events can happen in the same second
+    let seq = audit.range((time, 0)..=(time, u32::MAX))?
+        .next_back()
+        .transpose()?
+        .map(|(key, _)| key.value().1 + 1)
+        .unwrap_or_default();
+    audit.insert((time, seq), &event)?;
+    info!(target: "audit", "{event:?}");
+    Ok(())
+}
+
+fn insert_user(tx: &WriteTransaction, username: String) -> Result<u16> {
+    let mut users = tx.open_table(USERS_TABLE)?;
+    let mut users_reverse = tx.open_table(USERS_TABLE_REVERSE)?;
+
+    // make sure this username isn't already used
+    if users_reverse.get(&*username)?.is_some() {
+        return Err(StoreError::UsernameInUse);
+    }
+    
+    // add one to last key
+    let user_id = users.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
+    users_reverse.insert(&*username, user_id)?;
+    users.insert(user_id, username)?;
+    Ok(user_id)
+}
+
+pub struct Store {
+    db: Database,
+}
+
+impl Store {
+    /// initialize the store on the given path, or in memory if `path` is None
+    pub fn init<P: AsRef<Path>>(path: Option<P>) -> Result<Self> {
+        let db = if let Some(path) = path {
+            let file = OpenOptions::new()
+                .write(true)
+                .read(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o600)
+                .open(path)?;
+            Database::builder().create_file(file)?
+        } else {
+            Database::builder().create_with_backend(InMemoryBackend::new())?
+        };
+        let store = Self { db };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    /// upgrade tables written by older versions
+    fn migrate(&self) -> Result<()> {
+        let tx = self.db.begin_write()?;
+
+        // groups used to be (name, members) with no roles
+        let legacy_groups = match tx.open_table(LEGACY_GROUPS_TABLE) {
+            Ok(table) => Some(
+                table.iter()?
+                    .map(|v| {
+                        let v = v?;
+                        let (name, members) = v.1.value();
+                        Ok((v.0.value(), name, members))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            ),
+            // either there are no groups yet or they've already been migrated
+            Err(redb::TableError::TableDoesNotExist(_) | redb::TableError::TableTypeMismatch { .. }) => None,
+            Err(e) => return Err(e.into())
+        };
+        if let Some(legacy_groups) = legacy_groups {
+            tx.delete_table(LEGACY_GROUPS_TABLE)?;
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            for (id, name, members) in legacy_groups {
+                // every member could do everything before, so keep it that way
+                let members = members.into_iter().map(|m| (m, GroupRole::Owner)).collect();
+                groups.insert(id, Group { name, members, public: false })?;
+            }
+        }
+
+        // reminders used to have the same type name as messages
+        let legacy_reminders = match tx.open_table(LEGACY_REMINDERS_TABLE) {
+            Ok(table) => Some(
+                table.iter()?
+                    .map(|v| {
+                        let v = v?;
+                        Ok((v.0.value(), v.1.value()))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            ),
+            // either there are no reminders yet or they've already been migrated
+            Err(redb::TableError::TableDoesNotExist(_) | redb::TableError::TableTypeMismatch { .. }) => None,
+            Err(e) => return Err(e.into())
+        };
+        if let Some(legacy_reminders) = legacy_reminders {
+            tx.delete_table(LEGACY_REMINDERS_TABLE)?;
+            let mut reminders = tx.open_table(REMINDERS_TABLE)?;
+            for (id, reminder) in legacy_reminders {
+                reminders.insert(id, reminder)?;
+            }
+        }
+
+        // mutes used to only be looked up by conversation
+        {
+            let mutes = tx.open_table(MUTES_TABLE)?;
+            let mut mutes_reverse = tx.open_table(MUTES_TABLE_REVERSE)?;
+            if mutes_reverse.is_empty()? {
+                for v in mutes.iter()? {
+                    let (conversation, user_id) = v?.0.value();
+                    mutes_reverse.insert((user_id, conversation), ())?;
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_username_for_id(&self, id: u16) -> Result<Option<String>> {
+        let tx = self.db.begin_read()?;
+        let users = ignore_nonexistent_table!(tx.open_table(USERS_TABLE), Ok(None))?;
+        Ok(users.get(id)?.map(|v| v.value()))
+    }
+
+    pub fn get_id_for_username(&self, username: &str) -> Result<Option<u16>> {
+        let tx = self.db.begin_read()?;
+        let users = ignore_nonexistent_table!(tx.open_table(USERS_TABLE_REVERSE), Ok(None))?;
+        Ok(users.get(username)?.map(|v| v.value()))
+    }
+
+    pub fn create_user(&self, username: String) -> Result<u16> {
+        let tx = self.db.begin_write()?;
+        let user_id = insert_user(&tx, username)?;
+        insert_audit_event(&tx, AuditEvent::UserCreated { user: user_id })?;
+        tx.commit()?;
+        Ok(user_id)
+    }
+
+    pub fn get_user_details(&self, user_id: u16) -> Result<UserDetails> {
+        let tx = self.db.begin_read()?;
+        let users = ignore_nonexistent_table!(tx.open_table(USERS_TABLE), Err(StoreError::InvalidUserIds))?;
+        if users.get(user_id)?.is_none() {
+            return Err(StoreError::InvalidUserIds);
+        }
+
+        let details = ignore_nonexistent_table!(tx.open_table(USER_DETAILS_TABLE), Ok(UserDetails::default()))?;
+        Ok(details.get(user_id)?.map(|d| d.value()).unwrap_or_default())
+    }
+
+    /// details for every user that has any
+    pub fn list_user_details(&self) -> Result<HashMap<u16, UserDetails>> {
+        let tx = self.db.begin_read()?;
+        let details = ignore_nonexistent_table!(tx.open_table(USER_DETAILS_TABLE), Ok(HashMap::new()))?;
+        Ok(details
+            .iter()?
+            .filter_map(|v| {
+                let v = v.ok()?;
+                Some((v.0.value(), v.1.value()))
+            })
+            .collect())
+    }
+
+    /// update a user's profile
+    ///
+    /// `None` leaves a field unchanged and an empty string clears it.
+    /// `avatar` is `(content type, data)`, with empty data removing the avatar
+    pub fn update_profile(
+        &self,
+        user_id: u16,
+        display_name: Option<String>,
+        status: Option<String>,
+        avatar: Option<(&str, &[u8])>
+    ) -> Result<UserDetails> {
+        let tx = self.db.begin_write()?;
+        let details;
+        {
+            let users = tx.open_table(USERS_TABLE)?;
+            if users.get(user_id)?.is_none() {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            let mut details_table = tx.open_table(USER_DETAILS_TABLE)?;
+            let mut updated = details_table.get(user_id)?.map(|d| d.value()).unwrap_or_default();
+            if updated.deleted {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            if let Some(display_name) = display_name {
+                updated.display_name = Some(display_name).filter(|n| !n.is_empty());
+            }
+            if let Some(status) = status {
+                updated.status = Some(status).filter(|s| !s.is_empty());
+            }
+            if let Some((content_type, data)) = avatar {
+                let mut avatars = tx.open_table(AVATARS_TABLE)?;
+                if data.is_empty() {
+                    avatars.remove(user_id)?;
+                    updated.avatar_updated = None;
+                } else {
+                    avatars.insert(user_id, (content_type, data))?;
+                    updated.avatar_updated = Some(Utc::now().timestamp());
+                }
+            }
+
+            details_table.insert(user_id, &updated)?;
+            details = updated;
+        }
+        tx.commit()?;
+        Ok(details)
+    }
+
+    /// get a user's avatar as (content type, data)
+    pub fn get_avatar(&self, user_id: u16) -> Result<Option<(String, Vec<u8>)>> {
+        let tx = self.db.begin_read()?;
+        let avatars = ignore_nonexistent_table!(tx.open_table(AVATARS_TABLE), Ok(None))?;
+        Ok(avatars.get(user_id)?.map(|a| {
+            let (content_type, data) = a.value();
+            (content_type.to_owned(), data.to_owned())
+        }))
+    }
+
+    /// change a user's username, keeping the reverse lookup in sync. `by` is the admin doing it
+    pub fn rename_user(&self, user_id: u16, username: String, by: u16) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        {
+            let mut users = tx.open_table(USERS_TABLE)?;
+            let mut users_reverse = tx.open_table(USERS_TABLE_REVERSE)?;
+            let old_username = users.get(user_id)?
+                .ok_or(StoreError::InvalidUserIds)?
+                .value();
+
+            // deleted users don't have a username anymore
+            if users_reverse.get(&*old_username)?.map(|id| id.value()) != Some(user_id) {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            if let Some(existing) = users_reverse.get(&*username)? {
+                if existing.value() == user_id {
+                    // nothing to do
+                    return Ok(());
+                }
+                return Err(StoreError::UsernameInUse);
+            }
+
+            users_reverse.remove(&*old_username)?;
+            users_reverse.insert(&*username, user_id)?;
+            let action = format!("renamed user {user_id} to {username}");
+            users.insert(user_id, username)?;
+            insert_audit_event(&tx, AuditEvent::AdminAction { admin: by, action })?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// apply a change to the details of a user that hasn't been deleted, recording `event`
+    fn update_user_details(&self, user_id: u16, event: AuditEvent, update: impl FnOnce(&mut UserDetails)) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        update_user_details(&tx, user_id, update)?;
+        insert_audit_event(&tx, event)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn set_user_deactivated(&self, user_id: u16, deactivated: bool, by: u16) -> Result<()> {
+        let action = format!("{} user {user_id}", if deactivated { "deactivated" } else { "reactivated" });
+        self.update_user_details(user_id, AuditEvent::AdminAction { admin: by, action }, |details| details.deactivated = deactivated)
+    }
+
+    /// revoking admin rights also takes away the global reach of the user's tag webhooks
+    pub fn set_user_admin(&self, user_id: u16, admin: bool, by: u16) -> Result<()> {
+        let action = format!("{} admin rights for user {user_id}", if admin { "granted" } else { "revoked" });
+        let tx = self.db.begin_write()?;
+        update_user_details(&tx, user_id, |details| details.admin = admin)?;
+        if !admin {
+            let mut webhooks = tx.open_table(WEBHOOKS_TABLE)?;
+            let mut global = vec![];
+            for v in webhooks.iter()? {
+                let (id, webhook) = v?;
+                let webhook = webhook.value();
+                if webhook.owner == user_id && webhook.global {
+                    global.push((id.value(), webhook));
+                }
+            }
+            for (id, mut webhook) in global {
+                webhook.global = false;
+                webhooks.insert(id, webhook)?;
+            }
+        }
+        insert_audit_event(&tx, AuditEvent::AdminAction { admin: by, action })?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// delete a user, either erasing their direct messages or leaving them attributed to a deleted user
+    ///
+    /// the user's id is never reused. they are removed from all of their groups, and the changed groups
+    /// are returned (`None` if the group was deleted because nobody was left). `by` is the admin doing it
+    pub fn delete_user(&self, user_id: u16, erase_messages: bool, by: u16) -> Result<Vec<(u16, Option<Group>)>> {
+        let tx = self.db.begin_write()?;
+        let changed_groups = delete_user(&tx, user_id, erase_messages, by)?;
+        let action = format!("deleted user {user_id}{}", if erase_messages { " and erased their messages" } else { "" });
+        insert_audit_event(&tx, AuditEvent::AdminAction { admin: by, action })?;
+        tx.commit()?;
+        Ok(changed_groups)
+    }
+
+    pub fn list_users(&self) -> Result<HashMap<u16, String>> {
+        let tx = self.db.begin_read()?;
+        let users = ignore_nonexistent_table!(tx.open_table(USERS_TABLE), Ok(HashMap::new()))?;
+        Ok(users
+           .iter()?
+           .filter_map(|v| {
+               let v = v.ok()?;
+               Some((v.0.value(), v.1.value()))
+           })
+           .collect())
+    }
+
+    /// create a group (with `user_id` as the owner) or update an existing one
+    ///
+    /// `public` makes a new group a public channel, and is ignored for existing groups. also returns
+    /// the users that were added and removed, as of this transaction
+    pub fn create_update_group(
+        &self,
+        name: String,
+        users: HashSet<u16>,
+        public: bool,
+        group_id: Option<u16>,
+        user_id: u16
+    ) -> Result<(u16, Group, Vec<u16>, Vec<u16>)> {
+        let tx = self.db.begin_write()?;
+        let id;
+        let group;
+        let added;
+        let removed;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let users_table = tx.open_table(USERS_TABLE)?;
+            // make sure all of the users exist
+            if !users
+                .iter()
+                .all(|user_id| users_table.get(user_id).is_ok_and(|v| v.is_some()))
+            {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            (id, group) = if let Some(id) = group_id {
+                // make sure this group exists, and only let its admins change it
+                let mut group = groups.get(id)?
+                    .ok_or(StoreError::InvalidGroupId)?
+                    .value();
+                group.require_role(user_id, GroupRole::Admin)?;
+
+                let old_members: HashSet<_> = group.members.keys().copied().collect();
+                (added, removed) = if old_members != users {
+                    let removed = group.remove_members(old_members.difference(&users).copied(), user_id)?;
+                    (group.add_members(users), removed)
+                } else {
+                    (vec![], vec![])
+                };
+
+                delete_group_webhooks(&tx, id, Some(&removed))?;
+                let change = format!("renamed to {name} (added {added:?}, removed {removed:?})");
+                insert_audit_event(&tx, AuditEvent::GroupEdited { group: id, by: user_id, change })?;
+                group.name = name;
+                (id, group)
+            } else {
+                let mut members: HashMap<_, _> = users.into_iter()
+                    .map(|id| (id, GroupRole::Member))
+                    .collect();
+                // the creator owns the group
+                members.insert(user_id, GroupRole::Owner);
+                
+                // add one to last key
+                let id = groups.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
+                insert_audit_event(&tx, AuditEvent::GroupCreated { group: id, by: user_id })?;
+                // everyone is new to it
+                (added, removed) = (members.keys().copied().collect(), vec![]);
+                (id, Group { name, members, public })
+            };
+
+            groups.insert(id, &group)?;
+        }
+        tx.commit()?;
+        Ok((id, group, added, removed))
+    }
+
+    /// change the role of a group member - only owners can do this
+    pub fn set_group_role(&self, group_id: u16, target: u16, role: GroupRole, user_id: u16) -> Result<Group> {
+        let tx = self.db.begin_write()?;
+        let group;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let mut updated = groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+            updated.require_role(user_id, GroupRole::Owner)?;
+
+            let target_role = updated.members.get_mut(&target).ok_or(StoreError::InvalidUserIds)?;
+            *target_role = role;
+            if !updated.has_owner() {
+                return Err(StoreError::LastOwner);
+            }
+
+            groups.insert(group_id, &updated)?;
+            let change = format!("set the role of user {target} to {role:?}");
+            insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: user_id, change })?;
+            group = updated;
+        }
+        tx.commit()?;
+        Ok(group)
+    }
+
+    /// add users to a group, returning the updated group and the users that were newly added
+    pub fn add_group_members(&self, group_id: u16, users: HashSet<u16>, user_id: u16) -> Result<(Group, Vec<u16>)> {
+        let tx = self.db.begin_write()?;
+        let group;
+        let added;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let users_table = tx.open_table(USERS_TABLE)?;
+            let mut updated = groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+            // only admins can change the membership
+            updated.require_role(user_id, GroupRole::Admin)?;
+
+            // make sure all of the users exist
+            if !users
+                .iter()
+                .all(|user_id| users_table.get(user_id).is_ok_and(|v| v.is_some()))
+            {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            added = updated.add_members(users);
+            groups.insert(group_id, &updated)?;
+            let change = format!("added {added:?}");
+            insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: user_id, change })?;
+            group = updated;
+        }
+        tx.commit()?;
+        Ok((group, added))
+    }
+
+    /// remove users from a group, returning the updated group and the users that were actually removed
+    pub fn remove_group_members(&self, group_id: u16, users: HashSet<u16>, user_id: u16) -> Result<(Group, Vec<u16>)> {
+        let tx = self.db.begin_write()?;
+        let group;
+        let removed;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let mut updated = groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+
+            removed = updated.remove_members(users, user_id)?;
+            groups.insert(group_id, &updated)?;
+            delete_group_webhooks(&tx, group_id, Some(&removed))?;
+            let change = format!("removed {removed:?}");
+            insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: user_id, change })?;
+            group = updated;
+        }
+        tx.commit()?;
+        Ok((group, removed))
+    }
+
+    /// remove this user from a group
+    ///
+    /// returns the remaining group, or None if they were the last member and the group was deleted
+    pub fn leave_group(&self, group_id: u16, user_id: u16) -> Result<Option<Group>> {
+        let tx = self.db.begin_write()?;
+        let group;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let mut updated = groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+            if updated.members.remove(&user_id).is_none() {
+                return Err(StoreError::PermissionDenied);
+            }
+
+            if updated.members.is_empty() {
+                // nobody is left to read it
+                groups.remove(group_id)?;
+                delete_group_messages(&tx, group_id)?;
+                delete_group_invites(&tx, group_id)?;
+                delete_group_webhooks(&tx, group_id, None)?;
+                insert_audit_event(&tx, AuditEvent::GroupDeleted { group: group_id, by: user_id })?;
+                group = None;
+            } else {
+                // they have to hand over ownership first
+                if !updated.has_owner() {
+                    return Err(StoreError::LastOwner);
+                }
+                groups.insert(group_id, &updated)?;
+                delete_group_webhooks(&tx, group_id, Some(&[user_id]))?;
+                insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: user_id, change: "left".into() })?;
+                group = Some(updated);
+            }
+        }
+        tx.commit()?;
+        Ok(group)
+    }
+
+    /// make a group a public channel (or make it private again) - only admins can do this
+    pub fn set_group_visibility(&self, group_id: u16, public: bool, user_id: u16) -> Result<Group> {
+        let tx = self.db.begin_write()?;
+        let group;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let mut updated = groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+            updated.require_role(user_id, GroupRole::Admin)?;
+
+            updated.public = public;
+            groups.insert(group_id, &updated)?;
+            let change = if public { "made public" } else { "made private" };
+            insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: user_id, change: change.into() })?;
+            group = updated;
+        }
+        tx.commit()?;
+        Ok(group)
+    }
+
+    /// all public channels, whether or not the user is a member
+    pub fn get_public_channels(&self) -> Result<HashMap<u16, Group>> {
+        let tx = self.db.begin_read()?;
+        let groups = ignore_nonexistent_table!(tx.open_table(GROUPS_TABLE), Ok(HashMap::new()))?;
+
+        Ok(groups
+            .iter()?
+            .filter_map(|v| {
+                let v = v.ok()?;
+                let group = v.1.value();
+                if !group.public { return None };
+                Some((v.0.value(), group))
+            })
+            .collect())
+    }
+
+    /// join a public channel as a regular member
+    ///
+    /// returns the updated group and whether they were newly added
+    pub fn join_public_channel(&self, group_id: u16, user_id: u16) -> Result<(Group, bool)> {
+        let tx = self.db.begin_write()?;
+        let group;
+        let added;
+        {
+            let users = tx.open_table(USERS_TABLE)?;
+            if users.get(user_id)?.is_none() {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let mut updated = groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+            // private groups need an invite
+            if !updated.public {
+                return Err(StoreError::PermissionDenied);
+            }
+
+            added = !updated.add_members([user_id]).is_empty();
+            if added {
+                groups.insert(group_id, &updated)?;
+                insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: user_id, change: "joined".into() })?;
+            }
+            group = updated;
+        }
+        tx.commit()?;
+        Ok((group, added))
+    }
+
+    /// create an invite token for a group - only admins can do this
+    pub fn create_group_invite(&self, group_id: u16, user_id: u16, expires: Option<i64>, max_uses: Option<u32>) -> Result<(String, GroupInvite)> {
+        if max_uses == Some(0) {
+            return Err(StoreError::InvalidMaxUses);
+        }
+        let tx = self.db.begin_write()?;
+        let token: String = Alphanumeric.sample_string(&mut thread_rng(), 24);
+        let invite = GroupInvite { group: group_id, creator: user_id, expires, uses_left: max_uses };
+        {
+            let groups = tx.open_table(GROUPS_TABLE)?;
+            groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value()
+                .require_role(user_id, GroupRole::Admin)?;
+
+            let mut invites = tx.open_table(GROUP_INVITES_TABLE)?;
+            invites.insert(&*token, &invite)?;
+        }
+        tx.commit()?;
+        Ok((token, invite))
+    }
+
+    /// list the usable invites for a group - only admins can do this
+    pub fn get_group_invites(&self, group_id: u16, user_id: u16) -> Result<Vec<(String, GroupInvite)>> {
+        let tx = self.db.begin_read()?;
+        let groups = ignore_nonexistent_table!(tx.open_table(GROUPS_TABLE), Err(StoreError::InvalidGroupId))?;
+        groups.get(group_id)?
+            .ok_or(StoreError::InvalidGroupId)?
+            .value()
+            .require_role(user_id, GroupRole::Admin)?;
+
+        let invites = ignore_nonexistent_table!(tx.open_table(GROUP_INVITES_TABLE), Ok(vec![]))?;
+        let now = Utc::now().timestamp();
+        invites
+            .iter()?
+            .filter_map(|v| {
+                let v = match v {
+                    Ok(v) => v,
+                    Err(e) => return Some(Err(e.into()))
+                };
+                let invite = v.1.value();
+                (invite.group == group_id && invite.is_valid(now))
+                    .then(|| Ok((v.0.value().to_owned(), invite)))
+            })
+            .collect()
+    }
+
+    /// revoke an invite - only admins of the group can do this
+    pub fn revoke_group_invite(&self, token: &str, user_id: u16) -> Result<GroupInvite> {
+        let tx = self.db.begin_write()?;
+        let invite;
+        {
+            let mut invites = tx.open_table(GROUP_INVITES_TABLE)?;
+            invite = invites.remove(token)?
+                .ok_or(StoreError::InvalidInvite)?
+                .value();
+
+            let groups = tx.open_table(GROUPS_TABLE)?;
+            groups.get(invite.group)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value()
+                .require_role(user_id, GroupRole::Admin)?;
+        }
+        tx.commit()?;
+        Ok(invite)
+    }
+
+    /// join a group with an invite token
+    ///
+    /// returns the group id, the updated group and whether they were newly added
+    pub fn redeem_group_invite(&self, token: &str, user_id: u16) -> Result<(u16, Group, bool)> {
+        let tx = self.db.begin_write()?;
+        let group_id;
+        let group;
+        let added;
+        {
+            let users = tx.open_table(USERS_TABLE)?;
+            if users.get(user_id)?.is_none() {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            let mut invites = tx.open_table(GROUP_INVITES_TABLE)?;
+            let mut invite = invites.get(token)?
+                .ok_or(StoreError::InvalidInvite)?
+                .value();
+            if !invite.is_valid(Utc::now().timestamp()) {
+                // clean it up while we're here
+                invites.remove(token)?;
+                drop(invites);
+                drop(users);
+                tx.commit()?;
+                return Err(StoreError::InvalidInvite);
+            }
+
+            group_id = invite.group;
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            let mut updated = groups.get(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+
+            // existing members don't use up the invite
+            added = !updated.add_members([user_id]).is_empty();
+            if added {
+                groups.insert(group_id, &updated)?;
+                let change = format!("joined with an invite from user {}", invite.creator);
+                insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: user_id, change })?;
+
+                match &mut invite.uses_left {
+                    Some(1) => { invites.remove(token)?; },
+                    Some(uses_left) => {
+                        *uses_left -= 1;
+                        invites.insert(token, &invite)?;
+                    },
+                    None => {}
+                }
+            }
+            group = updated;
+        }
+        tx.commit()?;
+        Ok((group_id, group, added))
+    }
+
+    /// delete a group. server admins (`as_admin`) can delete any group
+    pub fn delete_group(&self, group_id: u16, user_id: u16, as_admin: bool) -> Result<HashSet<u16>> {
+        let tx = self.db.begin_write()?;
+        let group;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            group = groups.remove(group_id)?
+                .ok_or(StoreError::InvalidGroupId)?
+                .value();
+            // make sure they are allowed to delete this group
+            if !as_admin {
+                group.require_role(user_id, GroupRole::Admin)?;
+            }
+        }
+        delete_group_messages(&tx, group_id)?;
+        delete_group_invites(&tx, group_id)?;
+        delete_group_webhooks(&tx, group_id, None)?;
+        insert_audit_event(&tx, AuditEvent::GroupDeleted { group: group_id, by: user_id })?;
+        tx.commit()?;
+        Ok(group.members.into_keys().collect())
+    }
+
+    pub fn get_group(&self, group_id: u16) -> Result<Option<Group>> {
+        let tx = self.db.begin_read()?;
+        let groups = ignore_nonexistent_table!(tx.open_table(GROUPS_TABLE), Ok(None))?;
+        Ok(groups.get(group_id)?.map(|g| g.value()))
+    }
+
+    pub fn get_group_members(&self, group_id: u16) -> Result<Option<HashSet<u16>>> {
+        Ok(self.get_group(group_id)?
+            .map(|g| g.members.into_keys().collect()))
+    }
+
+    pub fn set_user_blocked(&self, user_id: u16, blocked_user: u16, blocked: bool) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        {
+            let users = tx.open_table(USERS_TABLE)?;
+            if user_id == blocked_user || users.get(blocked_user)?.is_none() {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            let mut blocks = tx.open_table(BLOCKS_TABLE)?;
+            if blocked {
+                blocks.insert((user_id, blocked_user), ())?;
+            } else {
+                blocks.remove((user_id, blocked_user))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// get the users that `user_id` has blocked
+    pub fn get_blocked_users(&self, user_id: u16) -> Result<HashSet<u16>> {
+        let tx = self.db.begin_read()?;
+        let blocks = ignore_nonexistent_table!(tx.open_table(BLOCKS_TABLE), Ok(HashSet::new()))?;
+
+        Ok(blocks
+            .range((user_id, 0)..=(user_id, u16::MAX))?
+            .filter_map(|v| Some(v.ok()?.0.value().1))
+            .collect())
+    }
+
+    /// mute or unmute a conversation for `user_id`. muted conversations still receive messages
+    pub fn set_conversation_muted(&self, user_id: u16, conversation: MessageRecipient, muted: bool) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        {
+            // they can only mute conversations they could take part in
+            match conversation {
+                MessageRecipient::User(other) => {
+                    let users = tx.open_table(USERS_TABLE)?;
+                    if other == user_id || users.get(other)?.is_none() {
+                        return Err(StoreError::InvalidUserIds);
+                    }
+                },
+                MessageRecipient::Group(group_id) => {
+                    let groups = tx.open_table(GROUPS_TABLE)?;
+                    let group = groups.get(group_id)?
+                        .ok_or(StoreError::InvalidGroupId)?
+                        .value();
+                    if !group.is_member(user_id) {
+                        return Err(StoreError::PermissionDenied);
+                    }
+                }
+            }
+
+            let mut mutes = tx.open_table(MUTES_TABLE)?;
+            let mut mutes_reverse = tx.open_table(MUTES_TABLE_REVERSE)?;
+            if muted {
+                mutes.insert((conversation, user_id), ())?;
+                mutes_reverse.insert((user_id, conversation), ())?;
+            } else {
+                mutes.remove((conversation, user_id))?;
+                mutes_reverse.remove((user_id, conversation))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// get the conversations that `user_id` has muted
+    pub fn get_muted_conversations(&self, user_id: u16) -> Result<Vec<MessageRecipient>> {
+        let tx = self.db.begin_read()?;
+        let mutes = ignore_nonexistent_table!(tx.open_table(MUTES_TABLE_REVERSE), Ok(vec![]))?;
+
+        let first = MessageRecipient::User(u16::MIN);
+        let last = MessageRecipient::Group(u16::MAX);
+        Ok(mutes
+            .range((user_id, first)..=(user_id, last))?
+            .filter_map(|v| Some(v.ok()?.0.value().1))
+            .collect())
+    }
+
+    /// get the users who muted the conversation that a message from `sender` to `recipient` is in
+    pub fn get_muting_users(&self, sender: u16, recipient: MessageRecipient) -> Result<HashSet<u16>> {
+        let tx = self.db.begin_read()?;
+        let mutes = ignore_nonexistent_table!(tx.open_table(MUTES_TABLE), Ok(HashSet::new()))?;
+
+        // the recipient of a direct message sees it in their conversation with the sender
+        let conversation = match recipient {
+            MessageRecipient::User(_) => MessageRecipient::User(sender),
+            group => group
+        };
+        Ok(mutes
+            .range((conversation, 0)..=(conversation, u16::MAX))?
+            .filter_map(|v| Some(v.ok()?.0.value().1))
+            .filter(|user| match recipient {
+                MessageRecipient::User(recipient) => *user == recipient,
+                MessageRecipient::Group(_) => true
+            })
+            .collect())
+    }
+
+    /// get the stored VAPID private key, or store the one from `generate` if there isn't one yet
+    pub fn get_or_create_vapid_key(&self, generate: impl FnOnce() -> Vec<u8>) -> Result<Vec<u8>> {
+        let tx = self.db.begin_write()?;
+        let key;
+        {
+            let mut keys = tx.open_table(VAPID_KEY_TABLE)?;
+            let existing = keys.get(())?.map(|k| k.value().to_owned());
+            key = match existing {
+                Some(key) => key,
+                None => {
+                    let key = generate();
+                    keys.insert((), key.as_slice())?;
+                    key
+                }
+            };
+        }
+        tx.commit()?;
+        Ok(key)
+    }
+
+    /// add (or update) a push subscription for a user
+    pub fn add_push_subscription(&self, user_id: u16, subscription: &PushSubscription) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        {
+            let users = tx.open_table(USERS_TABLE)?;
+            if users.get(user_id)?.is_none() {
+                return Err(StoreError::InvalidUserIds);
+            }
+
+            let mut subscriptions = tx.open_table(PUSH_SUBSCRIPTIONS_TABLE)?;
+            subscriptions.insert(
+                (user_id, subscription.endpoint.as_str()),
+                (subscription.p256dh.as_str(), subscription.auth.as_str())
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_push_subscription(&self, user_id: u16, endpoint: &str) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        tx.open_table(PUSH_SUBSCRIPTIONS_TABLE)?.remove((user_id, endpoint))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_push_subscriptions(&self, user_id: u16) -> Result<Vec<PushSubscription>> {
+        let tx = self.db.begin_read()?;
+        let subscriptions = ignore_nonexistent_table!(tx.open_table(PUSH_SUBSCRIPTIONS_TABLE), Ok(vec![]))?;
+
+        Ok(subscriptions
+            .range((user_id, "")..)?
+            .filter_map(|v| v.ok())
+            .take_while(|(key, _)| key.value().0 == user_id)
+            .map(|(key, value)| {
+                let (p256dh, auth) = value.value();
+                PushSubscription { endpoint: key.value().1.to_owned(), p256dh: p256dh.to_owned(), auth: auth.to_owned() }
+            })
+            .collect())
+    }
+
+    /// create an incoming webhook that posts as a new bot user named `bot_name`
+    ///
+    /// group admins can create them for their groups (the bot joins the group), and anyone can create
+    /// one for direct messages to themselves. returns the group if the bot was added to one
+    pub fn create_incoming_webhook(&self, creator: u16, recipient: MessageRecipient, bot_name: String) -> Result<(String, IncomingWebhook, Option<Group>)> {
+        let tx = self.db.begin_write()?;
+        let token: String = Alphanumeric.sample_string(&mut thread_rng(), 32);
+        let webhook;
+        let mut group = None;
+        {
+            let mut groups = tx.open_table(GROUPS_TABLE)?;
+            match recipient {
+                MessageRecipient::Group(group_id) => {
+                    let existing = groups.get(group_id)?
+                        .ok_or(StoreError::InvalidGroupId)?
+                        .value();
+                    existing.require_role(creator, GroupRole::Admin)?;
+                    group = Some(existing);
+                },
+                MessageRecipient::User(user_id) => {
+                    if user_id != creator {
+                        return Err(StoreError::PermissionDenied);
+                    }
+                }
+            }
+
+            let bot = insert_user(&tx, bot_name)?;
+            let details = UserDetails { bot: true, ..Default::default() };
+            tx.open_table(USER_DETAILS_TABLE)?.insert(bot, details)?;
+
+            insert_audit_event(&tx, AuditEvent::UserCreated { user: bot })?;
+            if let (MessageRecipient::Group(group_id), Some(group)) = (recipient, &mut group) {
+                group.add_members([bot]);
+                groups.insert(group_id, &*group)?;
+                let change = format!("added [{bot}] (incoming webhook)");
+                insert_audit_event(&tx, AuditEvent::GroupEdited { group: group_id, by: creator, change })?;
+            }
+
+            webhook = IncomingWebhook { bot, recipient, creator };
+            tx.open_table(INCOMING_WEBHOOKS_TABLE)?.insert(&*token, &webhook)?;
+        }
+        tx.commit()?;
+        Ok((token, webhook, group))
+    }
+
+    /// list the incoming webhooks created by a user, or all of them if `creator` is None
+    ///
+    /// tokens are only handed out when the webhook is created, so they aren't included
+    pub fn get_incoming_webhooks(&self, creator: Option<u16>) -> Result<Vec<IncomingWebhook>> {
+        let tx = self.db.begin_read()?;
+        let webhooks = ignore_nonexistent_table!(tx.open_table(INCOMING_WEBHOOKS_TABLE), Ok(vec![]))?;
+
+        Ok(webhooks
+            .iter()?
+            .filter_map(|v| {
+                let webhook = v.ok()?.1.value();
+                if creator.is_some_and(|creator| creator != webhook.creator) {
+                    return None;
+                }
+                Some(webhook)
+            })
+            .collect())
+    }
+
+    /// revoke the token of the incoming webhook that posts as `bot`. the bot user stays so its
+    /// messages still have a sender
+    pub fn delete_incoming_webhook(&self, bot: u16, user_id: u16, as_admin: bool) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        {
+            let mut webhooks = tx.open_table(INCOMING_WEBHOOKS_TABLE)?;
+            let (token, webhook) = webhooks.iter()?
+                .find_map(|v| {
+                    let (token, webhook) = v.ok()?;
+                    let webhook = webhook.value();
+                    (webhook.bot == bot).then(|| (token.value().to_owned(), webhook))
+                })
+                .ok_or(StoreError::InvalidWebhookId)?;
+            if webhook.creator != user_id {
+                if !as_admin {
+                    return Err(StoreError::PermissionDenied);
+                }
+                let action = format!("deleted incoming webhook for bot {bot} of user {}", webhook.creator);
+                insert_audit_event(&tx, AuditEvent::AdminAction { admin: user_id, action })?;
+            }
+            webhooks.remove(&*token)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// post a message through an incoming webhook, as its bot user
+    pub fn post_incoming_webhook(&self, token: &str, message: String) -> Result<(u16, Message)> {
+        let webhook = {
+            let tx = self.db.begin_read()?;
+            let webhooks = ignore_nonexistent_table!(tx.open_table(INCOMING_WEBHOOKS_TABLE), Err(StoreError::InvalidWebhookId))?;
+            webhooks.get(token)?
+                .ok_or(StoreError::InvalidWebhookId)?
+                .value()
+        };
+
+        // direct message webhooks post to their creator
+        self.send_message(message, webhook.bot, webhook.recipient)
+    }
+
+    /// create a bot account owned by `owner`. bots can't create other bots
+    pub fn create_bot(&self, owner: u16, name: String) -> Result<(u16, Bot)> {
+        let tx = self.db.begin_write()?;
+        let bot;
+        let bot_id;
+        {
+            let mut details_table = tx.open_table(USER_DETAILS_TABLE)?;
+            let owner_details = details_table.get(owner)?.map(|d| d.value()).unwrap_or_default();
+            if owner_details.bot {
+                return Err(StoreError::PermissionDenied);
+            }
+
+            bot_id = insert_user(&tx, name)?;
+            details_table.insert(bot_id, UserDetails { bot: true, ..Default::default() })?;
+
+            bot = Bot { owner, token: Alphanumeric.sample_string(&mut thread_rng(), 32) };
+            tx.open_table(BOTS_TABLE)?.insert(bot_id, &bot)?;
+            tx.open_table(BOT_TOKENS_TABLE)?.insert(&*bot.token, bot_id)?;
+            insert_audit_event(&tx, AuditEvent::UserCreated { user: bot_id })?;
+        }
+        tx.commit()?;
+        Ok((bot_id, bot))
+    }
+
+    /// list the bots owned by a user, or all of them if `owner` is None
+    pub fn get_bots(&self, owner: Option<u16>) -> Result<Vec<(u16, Bot)>> {
+        let tx = self.db.begin_read()?;
+        let bots = ignore_nonexistent_table!(tx.open_table(BOTS_TABLE), Ok(vec![]))?;
+
+        Ok(bots
+            .iter()?
+            .filter_map(|v| {
+                let v = v.ok()?;
+                let bot = v.1.value();
+                if owner.is_some_and(|owner| owner != bot.owner) {
+                    return None;
+                }
+                Some((v.0.value(), bot))
+            })
+            .collect())
+    }
+
+    /// delete a bot account. its owner can do this, or a server admin (`as_admin`) for any bot
+    pub fn delete_bot(&self, bot_id: u16, user_id: u16, as_admin: bool) -> Result<Vec<(u16, Option<Group>)>> {
+        let tx = self.db.begin_write()?;
+        let owner = tx.open_table(BOTS_TABLE)?
+            .get(bot_id)?
+            .ok_or(StoreError::InvalidUserIds)?
+            .value()
+            .owner;
+        if !as_admin && owner != user_id {
+            return Err(StoreError::PermissionDenied);
+        }
+
+        let changed_groups = delete_user(&tx, bot_id, false, user_id)?;
+        if owner != user_id {
+            let action = format!("deleted bot {bot_id} of user {owner}");
+            insert_audit_event(&tx, AuditEvent::AdminAction { admin: user_id, action })?;
+        }
+        tx.commit()?;
+        Ok(changed_groups)
+    }
+
+    /// replace a bot's API token, logging out anything using the old one
+    pub fn reset_bot_token(&self, bot_id: u16, user_id: u16, as_admin: bool) -> Result<Bot> {
+        let tx = self.db.begin_write()?;
+        let bot;
+        {
+            let mut bots = tx.open_table(BOTS_TABLE)?;
+            let mut tokens = tx.open_table(BOT_TOKENS_TABLE)?;
+            let mut existing = bots.get(bot_id)?
+                .ok_or(StoreError::InvalidUserIds)?
+                .value();
+            if existing.owner != user_id {
+                if !as_admin {
+                    return Err(StoreError::PermissionDenied);
+                }
+                let action = format!("reset the token of bot {bot_id} of user {}", existing.owner);
+                insert_audit_event(&tx, AuditEvent::AdminAction { admin: user_id, action })?;
+            }
+
+            tokens.remove(&*existing.token)?;
+            existing.token = Alphanumeric.sample_string(&mut thread_rng(), 32);
+            tokens.insert(&*existing.token, bot_id)?;
+            bots.insert(bot_id, &existing)?;
+            bot = existing;
+        }
+        tx.commit()?;
+        Ok(bot)
+    }
+
+    /// the bot user an API token belongs to
+    pub fn get_bot_for_token(&self, token: &str) -> Result<u16> {
+        let tx = self.db.begin_read()?;
+        let tokens = ignore_nonexistent_table!(tx.open_table(BOT_TOKENS_TABLE), Err(StoreError::InvalidBotToken))?;
+        Ok(tokens.get(token)?
+            .ok_or(StoreError::InvalidBotToken)?
+            .value())
+    }
+
+    /// register an outgoing webhook
+    ///
+    /// users can add webhooks to direct conversations they're part of and to groups they're an admin of.
+    /// server admins (`as_admin`) can add them to any group, and their tag webhooks see every conversation
+    pub fn create_webhook(&self, owner: u16, url: String, trigger: WebhookTrigger, as_admin: bool) -> Result<(u16, Webhook)> {
+        let tx = self.db.begin_write()?;
+        let secret = Alphanumeric.sample_string(&mut thread_rng(), 32);
+        let webhook = Webhook { owner, url, secret, trigger, global: as_admin };
+        let id;
+        {
+            let users = tx.open_table(USERS_TABLE)?;
+            if users.get(owner)?.is_none() {
+                return Err(StoreError::InvalidUserIds);
+            }
+            match &webhook.trigger {
+                WebhookTrigger::Conversation(MessageRecipient::User(other)) => {
+                    if *other == owner || users.get(*other)?.is_none() {
+                        return Err(StoreError::InvalidUserIds);
+                    }
+                },
+                WebhookTrigger::Conversation(MessageRecipient::Group(group_id)) => {
+                    let groups = tx.open_table(GROUPS_TABLE)?;
+                    let group = groups.get(*group_id)?
+                        .ok_or(StoreError::InvalidGroupId)?
+                        .value();
+                    if !as_admin {
+                        group.require_role(owner, GroupRole::Admin)?;
+                    }
+                },
+                WebhookTrigger::Tag(_) => {}
+            }
+
+            let mut webhooks = tx.open_table(WEBHOOKS_TABLE)?;
+            id = webhooks.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
+            webhooks.insert(id, &webhook)?;
+            insert_audit_event(&tx, AuditEvent::WebhookCreated { webhook: id, by: owner, url: webhook.url.clone() })?;
+        }
+        tx.commit()?;
+        Ok((id, webhook))
+    }
+
+    /// list the webhooks owned by a user, or all of them if `owner` is None
+    pub fn get_webhooks(&self, owner: Option<u16>) -> Result<Vec<(u16, Webhook)>> {
+        let tx = self.db.begin_read()?;
+        let webhooks = ignore_nonexistent_table!(tx.open_table(WEBHOOKS_TABLE), Ok(vec![]))?;
+
+        Ok(webhooks
+            .iter()?
+            .filter_map(|v| {
+                let v = v.ok()?;
+                let webhook = v.1.value();
+                if owner.is_some_and(|owner| owner != webhook.owner) {
+                    return None;
+                }
+                Some((v.0.value(), webhook))
+            })
+            .collect())
+    }
+
+    /// delete a webhook along with its pending deliveries. server admins (`as_admin`) can delete any webhook
+    pub fn delete_webhook(&self, webhook_id: u16, user_id: u16, as_admin: bool) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        {
+            let mut webhooks = tx.open_table(WEBHOOKS_TABLE)?;
+            let webhook = webhooks.get(webhook_id)?
+                .ok_or(StoreError::InvalidWebhookId)?
+                .value();
+            if webhook.owner != user_id {
+                if !as_admin {
+                    return Err(StoreError::PermissionDenied);
+                }
+                let action = format!("deleted webhook {webhook_id} of user {}", webhook.owner);
+                insert_audit_event(&tx, AuditEvent::AdminAction { admin: user_id, action })?;
+            }
+            webhooks.remove(webhook_id)?;
+
+            let mut queue = tx.open_table(WEBHOOK_QUEUE_TABLE)?;
+            queue.retain(|_, delivery| delivery.webhook != webhook_id)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
 
-impl Store {
-    /// initialize the store on the given path, or in memory if `path` is None
-    pub fn init<P: AsRef<Path>>(path: Option<P>) -> Result<Self> {
-        let db = if let Some(path) = path {
-            let file = OpenOptions::new()
-                .write(true)
-                .read(true)
-                .create(true)
-                .mode(0o600)
-                .open(path)?;
-            Database::builder().create_file(file)?
-        } else {
-            Database::builder().create_with_backend(InMemoryBackend::new())?
-        };
-        Ok(Self { db })
+    /// queue a delivery of `payload` to every webhook that matches `message`, returning how many were queued
+    pub fn queue_webhook_deliveries(&self, message: &Message, event: &str, payload: &str) -> Result<usize> {
+        let tx = self.db.begin_write()?;
+        let mut queued = 0;
+        {
+            let webhooks = ignore_nonexistent_table!(tx.open_table(WEBHOOKS_TABLE), Ok(0))?;
+            let mut queue = tx.open_table(WEBHOOK_QUEUE_TABLE)?;
+            let mut next_id = queue.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
+            let now = Utc::now().timestamp();
+
+            for v in webhooks.iter()? {
+                let (webhook_id, webhook) = v?;
+                if webhook.value().matches(&tx, message)? {
+                    let delivery = WebhookDelivery {
+                        webhook: webhook_id.value(),
+                        event: event.to_owned(),
+                        payload: payload.to_owned(),
+                        attempts: 0,
+                        next_attempt: now
+                    };
+                    queue.insert(next_id, delivery)?;
+                    next_id += 1;
+                    queued += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(queued)
     }
 
-    #[cfg(test)]
-    pub fn get_username_for_id(&self, id: u16) -> Result<Option<String>> {
+    /// get the deliveries that should be attempted at `now`, along with their webhooks
+    pub fn get_due_webhook_deliveries(&self, now: i64) -> Result<Vec<(u64, WebhookDelivery, Webhook)>> {
         let tx = self.db.begin_read()?;
-        let users = ignore_nonexistent_table!(tx.open_table(USERS_TABLE), Ok(None))?;
-        Ok(users.get(id)?.map(|v| v.value()))
+        let queue = ignore_nonexistent_table!(tx.open_table(WEBHOOK_QUEUE_TABLE), Ok(vec![]))?;
+        let webhooks = ignore_nonexistent_table!(tx.open_table(WEBHOOKS_TABLE), Ok(vec![]))?;
+
+        let mut due = vec![];
+        for v in queue.iter()? {
+            let (id, delivery) = v?;
+            let delivery = delivery.value();
+            if delivery.next_attempt > now {
+                continue;
+            }
+            if let Some(webhook) = webhooks.get(delivery.webhook)? {
+                due.push((id.value(), delivery, webhook.value()));
+            }
+        }
+        Ok(due)
     }
 
-    pub fn get_id_for_username(&self, username: &str) -> Result<Option<u16>> {
-        let tx = self.db.begin_read()?;
-        let users = ignore_nonexistent_table!(tx.open_table(USERS_TABLE_REVERSE), Ok(None))?;
-        Ok(users.get(username)?.map(|v| v.value()))
+    /// remove a delivery from the queue, either because it succeeded or because it was given up on
+    pub fn finish_webhook_delivery(&self, delivery_id: u64) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        tx.open_table(WEBHOOK_QUEUE_TABLE)?.remove(delivery_id)?;
+        tx.commit()?;
+        Ok(())
     }
 
-    pub fn create_user(&self, username: String) -> Result<u16> {
+    /// record a failed attempt and schedule the next one
+    pub fn retry_webhook_delivery(&self, delivery_id: u64, next_attempt: i64) -> Result<()> {
         let tx = self.db.begin_write()?;
-        let user_id;
         {
-            let mut users = tx.open_table(USERS_TABLE)?;
-            let mut users_reverse = tx.open_table(USERS_TABLE_REVERSE)?;
+            let mut queue = tx.open_table(WEBHOOK_QUEUE_TABLE)?;
+            let delivery = queue.get(delivery_id)?.map(|d| d.value());
+            if let Some(mut delivery) = delivery {
+                delivery.attempts += 1;
+                delivery.next_attempt = next_attempt;
+                queue.insert(delivery_id, delivery)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
 
-            // make sure this username isn't already used
-            if users_reverse.get(&*username)?.is_some() {
-                return Err(StoreError::UsernameInUse);
+    /// remind a user about a message they can read at `time`
+    pub fn create_reminder(&self, user_id: u16, message_id: u16, time: i64) -> Result<(u64, Reminder)> {
+        let tx = self.db.begin_write()?;
+        let id;
+        let reminder = Reminder { user: user_id, message: message_id, time };
+        {
+            let message = tx.open_table(MESSAGES_TABLE)?
+                .get(message_id)?
+                .map(|m| m.value())
+                .ok_or(StoreError::InvalidMessageId)?;
+            if !is_participant(&tx, &message, user_id)? {
+                return Err(StoreError::PermissionDenied);
             }
-            
-            // add one to last key
-            user_id = users.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
-            users_reverse.insert(&*username, user_id)?;
-            users.insert(user_id, username)?;
+
+            let mut reminders = tx.open_table(REMINDERS_TABLE)?;
+            id = reminders.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
+            reminders.insert(id, &reminder)?;
         }
         tx.commit()?;
-        Ok(user_id)
+        Ok((id, reminder))
     }
 
-    pub fn list_users(&self) -> Result<HashMap<u16, String>> {
+    /// a user's pending reminders, soonest first
+    pub fn get_reminders(&self, user_id: u16) -> Result<Vec<(u64, Reminder)>> {
         let tx = self.db.begin_read()?;
-        let users = ignore_nonexistent_table!(tx.open_table(USERS_TABLE), Ok(HashMap::new()))?;
-        Ok(users
-           .iter()?
-           .filter_map(|v| {
-               let v = v.ok()?;
-               Some((v.0.value(), v.1.value().into()))
-           })
-           .collect())
+        let reminders = ignore_nonexistent_table!(tx.open_table(REMINDERS_TABLE), Ok(vec![]))?;
+
+        let mut reminders: Vec<_> = reminders
+            .iter()?
+            .filter_map(|v| {
+                let v = v.ok()?;
+                let reminder = v.1.value();
+                (reminder.user == user_id).then(|| (v.0.value(), reminder))
+            })
+            .collect();
+        reminders.sort_by_key(|(id, reminder)| (reminder.time, *id));
+        Ok(reminders)
     }
 
-    pub fn create_update_group(
-        &self,
-        name: String,
-        users: HashSet<u16>,
-        group_id: Option<u16>,
-        user_id: u16
-    ) -> Result<u16> {
+    pub fn cancel_reminder(&self, reminder_id: u64, user_id: u16) -> Result<()> {
         let tx = self.db.begin_write()?;
-        let id;
         {
-            let mut groups = tx.open_table(GROUPS_TABLE)?;
-            id = if let Some(id) = group_id {
-                // make sure this group exists and has the user in it
-                if let Some(group) = groups.get(id)? {
-                    if !group.value().1.contains(&user_id) {
-                        return Err(StoreError::PermissionDenied);
-                    }
-                } else {
-                    return Err(StoreError::InvalidGroupId);
-                }
-                id
-            } else {
-                // add one to last key
-                groups.last()?.map(|v| v.0.value() + 1).unwrap_or_default()
-            };
-            
-            let users_table = tx.open_table(USERS_TABLE)?;
-            // make sure all of the users exist
-            if !users
-                .iter()
-                .all(|user_id| users_table.get(user_id).is_ok_and(|v| v.is_some()))
-            {
-                return Err(StoreError::InvalidUserIds);
+            let mut reminders = tx.open_table(REMINDERS_TABLE)?;
+            let reminder = reminders.get(reminder_id)?
+                .ok_or(StoreError::InvalidReminderId)?
+                .value();
+            if reminder.user != user_id {
+                return Err(StoreError::PermissionDenied);
             }
-
-            groups.insert(id, (name, users))?;
+            reminders.remove(reminder_id)?;
         }
         tx.commit()?;
-        Ok(id)
+        Ok(())
     }
 
-    /// delete a group and return the members
-    pub fn delete_group(&self, group_id: u16, user_id: u16) -> Result<HashSet<u16>> {
+    /// take the reminders that are due at `now` for the users in `users`, with their messages
+    ///
+    /// they are removed in the same transaction, so each one is only handed out once. reminders about
+    /// messages that were deleted, or that the user can't read anymore, are dropped
+    pub fn take_due_reminders(&self, now: i64, users: &HashSet<u16>) -> Result<Vec<(u64, Reminder, Message)>> {
         let tx = self.db.begin_write()?;
-        let group;
+        let mut due = vec![];
         {
-            let mut groups = tx.open_table(GROUPS_TABLE)?;
-            if let Some(group_guard) = groups.remove(group_id)? {
-                group = group_guard.value();
-                // make sure they are a member of this group
-                if !group.1.contains(&user_id) {
-                    return Err(StoreError::PermissionDenied);
+            let mut reminders = tx.open_table(REMINDERS_TABLE)?;
+            let taken = reminders.extract_if(|_, reminder| reminder.time <= now && users.contains(&reminder.user))?
+                .map(|v| v.map(|(id, reminder)| (id.value(), reminder.value())))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            if taken.is_empty() {
+                return Ok(due);
+            }
+
+            let messages = tx.open_table(MESSAGES_TABLE)?;
+            for (id, reminder) in taken {
+                let Some(message) = messages.get(reminder.message)?.map(|m| m.value()) else {
+                    continue;
+                };
+                if is_participant(&tx, &message, reminder.user)? {
+                    due.push((id, reminder, message));
                 }
-            } else {
-                return Err(StoreError::InvalidGroupId);
             }
+        }
+        tx.commit()?;
+        Ok(due)
+    }
 
-            let group = MessageRecipient::Group(group_id);
+    /// put back reminders that were taken but couldn't be delivered, e.g. because the user just
+    /// disconnected. they keep their ids unless those were given to new reminders in the meantime
+    pub fn restore_reminders(&self, restored: Vec<(u64, Reminder)>) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        {
+            let mut reminders = tx.open_table(REMINDERS_TABLE)?;
+            for (id, reminder) in restored {
+                let id = match reminders.get(id)? {
+                    Some(_) => reminders.last()?.map(|v| v.0.value() + 1).unwrap_or_default(),
+                    None => id
+                };
+                reminders.insert(id, reminder)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
 
-            // delete all messages ever received by this group
-            let mut msg_endpoints = tx.open_table(MSG_ENDPOINT_TABLE)?;
-            let mut messages = tx.open_table(MESSAGES_TABLE)?;
+    /// when the next reminder after `now` is due
+    pub fn next_reminder_time(&self, now: i64) -> Result<Option<i64>> {
+        let tx = self.db.begin_read()?;
+        let reminders = ignore_nonexistent_table!(tx.open_table(REMINDERS_TABLE), Ok(None))?;
 
-            // iterate through all messages received by this group
-            let messages_sent_to_group = msg_endpoints.extract_from_if((group, u16::MIN, u16::MIN)..=(group, u16::MAX, u16::MAX), |_, _| true)?;
-            for message in messages_sent_to_group {
-                let (message, _) = message?;
-                let (_, _, message_id) = message.value();
-                // delete the message
-                messages.remove(message_id)?;
+        let mut next = None;
+        for v in reminders.iter()? {
+            let time = v?.1.value().time;
+            if time > now {
+                next = Some(next.map_or(time, |next: i64| next.min(time)));
             }
         }
+        Ok(next)
+    }
+
+    /// record an event that doesn't change anything else in the store, like a login
+    pub fn record_audit(&self, event: AuditEvent) -> Result<()> {
+        let tx = self.db.begin_write()?;
+        insert_audit_event(&tx, event)?;
         tx.commit()?;
-        Ok(group.1)
+        Ok(())
     }
 
-    pub fn get_group_members(&self, group_id: u16) -> Result<Option<HashSet<u16>>> {
+    /// get audit log entries between `from` and `to` (inclusive), oldest first
+    pub fn get_audit_log(&self, from: Option<i64>, to: Option<i64>) -> Result<Vec<AuditEntry>> {
         let tx = self.db.begin_read()?;
-        let groups = ignore_nonexistent_table!(tx.open_table(GROUPS_TABLE), Ok(None))?;
-        Ok(groups.get(group_id)?
-            .map(|g| g.value().1.clone()))
+        let audit = ignore_nonexistent_table!(tx.open_table(AUDIT_TABLE), Ok(vec![]))?;
+
+        let from = from.unwrap_or(i64::MIN);
+        let to = to.unwrap_or(i64::MAX);
+        if from > to {
+            return Ok(vec![]);
+        }
+
+        Ok(audit
+            .range((from, 0)..=(to, u32::MAX))?
+            .filter_map(|v| {
+                let v = v.ok()?;
+                Some(AuditEntry { time: v.0.value().0, event: v.1.value() })
+            })
+            .collect())
+    }
+
+    pub fn get_stats(&self) -> Result<StoreStats> {
+        let tx = self.db.begin_read()?;
+
+        // tables that were never written to just count as empty
+        macro_rules! count {
+            ($table:expr) => {
+                match tx.open_table($table) {
+                    Ok(table) => table.len()?,
+                    Err(redb::TableError::TableDoesNotExist(_)) => 0,
+                    Err(e) => return Err(e.into())
+                }
+            }
+        }
+
+        Ok(StoreStats {
+            users: count!(USERS_TABLE),
+            groups: count!(GROUPS_TABLE),
+            messages: count!(MESSAGES_TABLE),
+            invites: count!(GROUP_INVITES_TABLE),
+            avatars: count!(AVATARS_TABLE)
+        })
+    }
+
+    /// get every group, regardless of membership
+    pub fn get_all_groups(&self) -> Result<HashMap<u16, Group>> {
+        let tx = self.db.begin_read()?;
+        let groups = ignore_nonexistent_table!(tx.open_table(GROUPS_TABLE), Ok(HashMap::new()))?;
+
+        Ok(groups
+            .iter()?
+            .filter_map(|v| {
+                let v = v.ok()?;
+                Some((v.0.value(), v.1.value()))
+            })
+            .collect())
     }
 
-    pub fn get_groups_for_user(&self, user_id: u16) -> Result<HashMap<u16, (String, HashSet<u16>)>> {
+    pub fn get_groups_for_user(&self, user_id: u16) -> Result<HashMap<u16, Group>> {
         let tx = self.db.begin_read()?;
 
         // make sure the user exists
@@ -271,8 +2134,8 @@ impl Store {
                 let v = v.ok()?;
                 let group = v.1.value();
                 // make sure they're a part of this group
-                if !group.1.contains(&user_id) { return None };
-                Some((v.0.value(), (group.0.into(), group.1)))
+                if !group.is_member(user_id) { return None };
+                Some((v.0.value(), group))
             })
             .collect())
     }
@@ -280,33 +2143,7 @@ impl Store {
     pub fn send_message(&self, message: String, sender: u16, recipient: MessageRecipient) -> Result<(u16, Message)> {
         let tx = self.db.begin_write()?;
 
-        let users = tx.open_table(USERS_TABLE)?;
-
-        // make sure the sender exists
-        if users.get(sender)?.is_none() {
-            return Err(StoreError::InvalidUserIds);
-        }
-
-        // make sure the recepient exists
-        match recipient {
-            MessageRecipient::Group(group_id) => {                
-                let groups = tx.open_table(GROUPS_TABLE)?;
-                if let Some(group) = groups.get(group_id)? {
-                    // make sure they're a member of this group
-                    let users = group.value().1;
-                    if !users.contains(&sender) {
-                        return Err(StoreError::PermissionDenied);
-                    }
-                } else {
-                    return Err(StoreError::InvalidGroupId);
-                };
-            },
-            MessageRecipient::User(user_id) => {
-                if users.get(user_id)?.is_none() {
-                    return Err(StoreError::InvalidUserIds);
-                };
-            }
-        }
+        check_can_send(&tx, sender, recipient)?;
 
         let time = Utc::now().timestamp();
         let message = Message {
@@ -314,23 +2151,46 @@ impl Store {
             sender,
             recipient,
             time,
-            tags: vec![]
+            tags: vec![],
+            forwarded: None
         };
 
-        let id;
+        let id = insert_message(&tx, &message)?;
 
-        {                
-            let mut messages = tx.open_table(MESSAGES_TABLE)?;
-            // add one to last key
-            id = messages.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
-            messages.insert(id, message.clone())?;
+        tx.commit()?;
+        Ok((id, message))
+    }
 
-            // add it to the endpoints table
-            let mut msg_endpoints = tx.open_table(MSG_ENDPOINT_TABLE)?;
-            msg_endpoints.insert((recipient, sender, id), ())?;
+    /// copy an existing message (including its tags) into another conversation
+    pub fn forward_message(&self, message_id: u16, user_id: u16, recipient: MessageRecipient) -> Result<(u16, Message)> {
+        let tx = self.db.begin_write()?;
+
+        let original = tx.open_table(MESSAGES_TABLE)?
+            .get(message_id)?
+            .map(|m| m.value())
+            .ok_or(StoreError::InvalidMessageId)?;
+
+        // they can only forward messages they can read
+        if !is_participant(&tx, &original, user_id)? {
+            return Err(StoreError::PermissionDenied);
         }
 
-        drop(users);
+        check_can_send(&tx, user_id, recipient)?;
+
+        let message = Message {
+            sender: user_id,
+            recipient,
+            time: Utc::now().timestamp(),
+            // keep pointing at the original author if this was already forwarded
+            forwarded: Some(original.forwarded.unwrap_or(ForwardedFrom {
+                sender: original.sender,
+                time: original.time
+            })),
+            message: original.message,
+            tags: original.tags
+        };
+
+        let id = insert_message(&tx, &message)?;
 
         tx.commit()?;
         Ok((id, message))
@@ -339,21 +2199,8 @@ impl Store {
     /// bypasses all restrictions
     #[allow(dead_code)]
     pub fn create_message(&self, message: Message) -> Result<()> {
-        let recipient = message.recipient;
-        let sender = message.sender;
-        
         let tx = self.db.begin_write()?;
-        let mut messages = tx.open_table(MESSAGES_TABLE)?;
-        // add one to last key
-        let id = messages.last()?.map(|v| v.0.value() + 1).unwrap_or_default();
-        messages.insert(id, message.clone())?;
-        
-        // add it to the endpoints table
-        let mut msg_endpoints = tx.open_table(MSG_ENDPOINT_TABLE)?;
-        msg_endpoints.insert((recipient, sender, id), ())?;
-        
-        drop(messages);
-        drop(msg_endpoints);
+        insert_message(&tx, &message)?;
         tx.commit()?;
         Ok(())
     }
@@ -412,7 +2259,8 @@ impl Store {
         Ok(message)
     }
 
-    pub fn delete_message(&self, message_id: u16, user_id: u16) -> Result<Option<Message>> {
+    /// delete a message. server admins (`as_admin`) can delete any message
+    pub fn delete_message(&self, message_id: u16, user_id: u16, as_admin: bool) -> Result<Option<Message>> {
         let tx = self.db.begin_write()?;
 
         let message;
@@ -423,31 +2271,18 @@ impl Store {
             
             // make sure they're allowed to delete this message
             message = messages.get(message_id)?.map(|a| a.value());
-            if let Some(Message { sender, recipient, .. }) = message {
-                if sender != user_id {
-                    // check if they're the recipient
-                    if !match recipient {
-                        MessageRecipient::User(recipient_user_id) => recipient_user_id == user_id,
-                        MessageRecipient::Group(group_id) => {
-                            let groups = tx.open_table(GROUPS_TABLE)?;
-                            let group = groups.get(group_id)?;
-                            if let Some(group) = group {
-                                // make sure they're a member of this group
-                                let users = group.value().1;
-                                users.contains(&user_id)
-                            } else {
-                                // group doesn't exist
-                                false
-                            }
-                        }
-                    } {
-                        // they're not the recipient
-                        return Err(StoreError::PermissionDenied);
-                    }
+            if let Some(message) = &message {
+                // senders and recipients can both delete
+                if !as_admin && !is_participant(&tx, message, user_id)? {
+                    return Err(StoreError::PermissionDenied);
                 }
                 // actually delete the message
                 messages.remove(message_id)?;
-                msg_endpoints.remove((recipient, sender, message_id))?;
+                msg_endpoints.remove((message.recipient, message.sender, message_id))?;
+                delete_message_reminders(&tx, &[message_id])?;
+                if message.sender != user_id {
+                    insert_audit_event(&tx, AuditEvent::MessageDeleted { message: message_id, sender: message.sender, by: user_id })?;
+                }
             } else {
                 return Err(StoreError::InvalidMessageId);
             }
@@ -466,7 +2301,7 @@ impl Store {
 
         if let Some(group) = groups.get(group_id)? {
             // make sure they're a member
-            if !group.value().1.contains(&user_id) {
+            if !group.value().is_member(user_id) {
                 return Err(StoreError::PermissionDenied);
             }
         } else {
@@ -476,7 +2311,6 @@ impl Store {
         let recipient = MessageRecipient::Group(group_id);
         let mut messages = msg_endpoints
             .range((recipient, u16::MIN, u16::MIN)..=(recipient, u16::MAX, u16::MAX))?
-            .into_iter()
             // get the message data
             .map(|message| -> Result<Option<(u16, Message)>> {
                 let message_id = message?.0.value().2;
@@ -505,12 +2339,10 @@ impl Store {
         let mut messages = msg_endpoints
             // messages from b -> a
             .range((user_a_recipient, user_b, u16::MIN)..=(user_a_recipient, user_b, u16::MAX))?
-            .into_iter()
             .chain(
                 // messages from a -> b
                 msg_endpoints
                     .range((user_b_recipient, user_a, u16::MIN)..=(user_b_recipient, user_a, u16::MAX))?
-                    .into_iter()
             )
             .map(|message| {
                 let message_id = message?.0.value().2;
@@ -531,6 +2363,71 @@ impl Store {
             MessageRecipient::Group(group_id) => self.get_group_messages(user_id, group_id)
         }
     }
+
+    /// get the newest message between the given user and recipient, only counting messages from
+    /// `sender` if it's set
+    ///
+    /// this only reads the end of each sender's range, rather than loading the whole conversation
+    pub fn get_last_message(&self, user_id: u16, recipient: MessageRecipient, sender: Option<u16>) -> Result<Option<(u16, Message)>> {
+        let tx = self.db.begin_read()?;
+        let messages = ignore_nonexistent_table!(tx.open_table(MESSAGES_TABLE), Ok(None))?;
+        let msg_endpoints = ignore_nonexistent_table!(tx.open_table(MSG_ENDPOINT_TABLE), Ok(None))?;
+
+        // the newest message with this recipient and sender
+        let last_from = |recipient: MessageRecipient, sender: u16| -> Result<Option<u16>> {
+            Ok(msg_endpoints
+                .range((recipient, sender, u16::MIN)..=(recipient, sender, u16::MAX))?
+                .last()
+                .transpose()?
+                .map(|message| message.0.value().2))
+        };
+
+        let last = match recipient {
+            MessageRecipient::User(other) => {
+                // direct messages are keyed by who received them
+                let mut last = None;
+                for (recipient, from) in [(MessageRecipient::User(other), user_id), (MessageRecipient::User(user_id), other)] {
+                    if sender.is_none() || sender == Some(from) {
+                        last = last.max(last_from(recipient, from)?);
+                    }
+                }
+                last
+            },
+            MessageRecipient::Group(group_id) => {
+                // make sure they're a member
+                let group = tx.open_table(GROUPS_TABLE)?
+                    .get(group_id)?
+                    .ok_or(StoreError::InvalidGroupId)?
+                    .value();
+                if !group.is_member(user_id) {
+                    return Err(StoreError::PermissionDenied);
+                }
+
+                if let Some(sender) = sender {
+                    last_from(recipient, sender)?
+                } else {
+                    // skip from one sender to the next, taking the end of each one's range
+                    let mut last = None;
+                    let mut next = Some(u16::MIN);
+                    while let Some(from) = next {
+                        let Some(first) = msg_endpoints
+                            .range((recipient, from, u16::MIN)..=(recipient, u16::MAX, u16::MAX))?
+                            .next()
+                            .transpose()? else { break };
+                        let (_, from, _) = first.0.value();
+                        last = last.max(last_from(recipient, from)?);
+                        next = from.checked_add(1);
+                    }
+                    last
+                }
+            }
+        };
+
+        let Some(message_id) = last else {
+            return Ok(None);
+        };
+        Ok(messages.get(message_id)?.map(|message| (message_id, message.value())))
+    }
 }
 
 #[cfg(test)]
@@ -539,12 +2436,18 @@ mod tests {
 
     use redb::ReadableTableMetadata;
 
-    use crate::store::{Message, MessageRecipient, StoreError};
+    use redb::{backends::InMemoryBackend, Database};
+
+    use crate::store::{AuditEvent, ForwardedFrom, Group, GroupInvite, GroupRole, Message, MessageRecipient, PushSubscription, Reminder, StoreError, StoreStats, UserDetails, WebhookTrigger};
 
-    use super::{Store, MESSAGES_TABLE, MSG_ENDPOINT_TABLE};
+    use super::{Store, DELETED_USERNAME, LEGACY_GROUPS_TABLE, LEGACY_REMINDERS_TABLE, MESSAGES_TABLE, MSG_ENDPOINT_TABLE, MUTES_TABLE};
 
     type Result<T=()> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+    fn group<const N: usize>(name: &str, members: [(u16, GroupRole); N]) -> Group {
+        Group { name: name.into(), members: HashMap::from(members), public: false }
+    }
+
     #[test]
     fn add_users() -> Result {
         let store = Store::init::<PathBuf>(None)?;
@@ -556,7 +2459,7 @@ mod tests {
 
         // make sure they exist
         assert_eq!(
-            store.get_username_for_id(0)?.as_ref().map(|a| a.as_str()),
+            store.get_username_for_id(0)?.as_deref(),
             Some("foobar")
         );
         assert_eq!(
@@ -569,7 +2472,7 @@ mod tests {
 
         // make sure they exist
         assert_eq!(
-            store.get_username_for_id(1)?.as_ref().map(|a| a.as_str()),
+            store.get_username_for_id(1)?.as_deref(),
             Some("foo")
         );
         assert_eq!(
@@ -592,58 +2495,192 @@ mod tests {
     }
 
     #[test]
-    fn add_groups() -> Result {
+    fn add_groups() -> Result {
+        let store = Store::init::<PathBuf>(None)?;
+
+        store.create_user("foobar".into())?;
+
+        // try creating the group with invalid users
+        assert!(matches!(
+            store.create_update_group("foo".into(), HashSet::from([0, 1]), false, None, 0),
+            Err(StoreError::InvalidUserIds)
+        ));
+
+        store.create_user("foo".into())?;
+
+        store.create_update_group("foo".into(), HashSet::from([1, 0]), false, None, 0)?;
+        store.create_update_group("foobar".into(), HashSet::from([1]), false, None, 1)?;
+
+        let group_0 = (0, group("foo", [(0, GroupRole::Owner), (1, GroupRole::Member)]));
+        let group_1 = (1, group("foobar", [(1, GroupRole::Owner)]));
+
+        // make sure we can read the groups for each user
+        assert_eq!(
+            store.get_groups_for_user(0)?,
+            HashMap::from([group_0.clone()])
+        );
+        assert_eq!(
+            store.get_groups_for_user(1)?,
+            HashMap::from([group_0, group_1])
+        );
+
+        // delete a group
+        assert!(matches!(
+            // user 0 cannot delete group 1
+            store.delete_group(1, 0, false),
+            Err(StoreError::PermissionDenied)
+        ));
+        assert!(matches!(
+            // user 1 is only a member of group 0
+            store.delete_group(0, 1, false),
+            Err(StoreError::InsufficientRole)
+        ));
+        store.delete_group(0, 0, false)?;
+
+        assert_eq!(store.get_groups_for_user(0)?, HashMap::new());
+
+        // edit group 1
+        assert!(matches!(
+            // user 0 cannot delete edit 1
+            store.create_update_group("bar".into(), HashSet::from([0]), false, Some(1), 0),
+            Err(StoreError::PermissionDenied)
+        ));
+        assert!(matches!(
+            // the only owner can't be removed
+            store.create_update_group("bar".into(), HashSet::from([0]), false, Some(1), 1),
+            Err(StoreError::LastOwner)
+        ));
+        store.create_update_group("bar".into(), HashSet::from([0, 1]), false, Some(1), 1)?;
+
+        assert_eq!(
+            store.get_groups_for_user(0)?,
+            HashMap::from([(1, group("bar", [(0, GroupRole::Member), (1, GroupRole::Owner)]))])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn group_roles() -> Result {
         let store = Store::init::<PathBuf>(None)?;
 
-        store.create_user("foobar".into())?;
+        for name in ["a", "b", "c", "d"] {
+            store.create_user(name.into())?;
+        }
 
-        // try creating the group with invalid users
+        // the creator is always the owner
+        let (id, ..) = store.create_update_group("foo".into(), HashSet::from([1, 2]), false, None, 0)?;
+        assert_eq!(store.get_group(id)?.map(|g| g.members[&0]), Some(GroupRole::Owner));
+
+        // members can't rename the group or change the membership
+        assert!(matches!(
+            store.create_update_group("bar".into(), HashSet::from([0, 1, 2]), false, Some(id), 1),
+            Err(StoreError::InsufficientRole)
+        ));
+        assert_eq!(store.get_group(id)?.map(|g| g.name), Some("foo".into()));
+        assert!(matches!(
+            store.create_update_group("bar".into(), HashSet::from([0, 1]), false, Some(id), 1),
+            Err(StoreError::InsufficientRole)
+        ));
+        assert!(matches!(
+            store.set_group_role(id, 1, GroupRole::Admin, 1),
+            Err(StoreError::InsufficientRole)
+        ));
         assert!(matches!(
-            store.create_update_group("foo".into(), HashSet::from([0, 1]), None, 0),
+            store.set_group_role(id, 3, GroupRole::Admin, 0),
             Err(StoreError::InvalidUserIds)
         ));
 
-        store.create_user("foo".into())?;
+        // admins can change the membership, but not remove owners
+        store.set_group_role(id, 1, GroupRole::Admin, 0)?;
+        let (_, _, added, removed) = store.create_update_group("bar".into(), HashSet::from([0, 1, 3]), false, Some(id), 1)?;
+        assert_eq!((added, removed), (vec![3], vec![2]));
+        assert!(matches!(
+            store.create_update_group("bar".into(), HashSet::from([1, 3]), false, Some(id), 1),
+            Err(StoreError::InsufficientRole)
+        ));
+        assert!(matches!(
+            store.set_group_role(id, 3, GroupRole::Admin, 1),
+            Err(StoreError::InsufficientRole)
+        ));
 
-        store.create_update_group("foo".into(), HashSet::from([1, 0]), None, 0)?;
-        store.create_update_group("foobar".into(), HashSet::from([1]), None, 1)?;
+        // there always has to be an owner
+        assert!(matches!(
+            store.set_group_role(id, 0, GroupRole::Member, 0),
+            Err(StoreError::LastOwner)
+        ));
+        store.set_group_role(id, 3, GroupRole::Owner, 0)?;
+        let group = store.set_group_role(id, 0, GroupRole::Member, 0)?;
+        assert_eq!(group, Group {
+            name: "bar".into(),
+            members: HashMap::from([(0, GroupRole::Member), (1, GroupRole::Admin), (3, GroupRole::Owner)]),
+            public: false
+        });
+
+        // admins can delete the group
+        assert!(matches!(store.delete_group(id, 0, false), Err(StoreError::InsufficientRole)));
+        store.delete_group(id, 1, false)?;
 
-        let group_0 = (0, ("foo".into(), HashSet::from([0, 1])));
-        let group_1 = (1, ("foobar".into(), HashSet::from([1])));
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_legacy_groups() -> Result {
+        let store = Store { db: Database::builder().create_with_backend(InMemoryBackend::new())? };
+
+        let tx = store.db.begin_write()?;
+        tx.open_table(LEGACY_GROUPS_TABLE)?.insert(3, ("foo".to_owned(), HashSet::from([1, 2])))?;
+        tx.commit()?;
+
+        store.migrate()?;
+        // running it again shouldn't do anything
+        store.migrate()?;
 
-        // make sure we can read the groups for each user
-        assert_eq!(
-            store.get_groups_for_user(0)?,
-            HashMap::from([group_0.clone()])
-        );
         assert_eq!(
-            store.get_groups_for_user(1)?,
-            HashMap::from([group_0, group_1])
+            store.get_group(3)?,
+            Some(group("foo", [(1, GroupRole::Owner), (2, GroupRole::Owner)]))
         );
 
-        // delete a group
-        assert!(matches!(
-            // user 0 cannot delete group 1
-            store.delete_group(1, 0),
-            Err(StoreError::PermissionDenied)
-        ));
-        store.delete_group(0, 1)?;
+        Ok(())
+    }
 
-        assert_eq!(store.get_groups_for_user(0)?, HashMap::new());
+    #[test]
+    fn migrate_legacy_reminders() -> Result {
+        let store = Store { db: Database::builder().create_with_backend(InMemoryBackend::new())? };
 
-        // edit group 1
-        assert!(matches!(
-            // user 0 cannot delete edit 1
-            store.create_update_group("bar".into(), HashSet::from([0]), Some(1), 0),
-            Err(StoreError::PermissionDenied)
-        ));
-        store.create_update_group("bar".into(), HashSet::from([0]), Some(1), 1)?;
+        let reminder = Reminder { user: 1, message: 2, time: 10 };
+        let tx = store.db.begin_write()?;
+        tx.open_table(LEGACY_REMINDERS_TABLE)?.insert(3, &reminder)?;
+        tx.commit()?;
+
+        store.migrate()?;
+        store.migrate()?;
+
+        assert_eq!(store.get_reminders(1)?, vec![(3, reminder)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_mutes() -> Result {
+        let store = Store { db: Database::builder().create_with_backend(InMemoryBackend::new())? };
+
+        let tx = store.db.begin_write()?;
+        let mut mutes = tx.open_table(MUTES_TABLE)?;
+        mutes.insert((MessageRecipient::Group(0), 1), ())?;
+        mutes.insert((MessageRecipient::User(2), 1), ())?;
+        mutes.insert((MessageRecipient::User(1), 2), ())?;
+        drop(mutes);
+        tx.commit()?;
+
+        store.migrate()?;
+        store.migrate()?;
 
         assert_eq!(
-            store.get_groups_for_user(0)?,
-            HashMap::from([(1, ("bar".into(), HashSet::from([0])))])
+            store.get_muted_conversations(1)?,
+            vec![MessageRecipient::User(2), MessageRecipient::Group(0)]
         );
-        assert_eq!(store.get_groups_for_user(1)?, HashMap::new());
+        assert_eq!(store.get_muted_conversations(2)?, vec![MessageRecipient::User(1)]);
 
         Ok(())
     }
@@ -656,8 +2693,8 @@ mod tests {
         store.create_user("c".into())?;
         store.create_user("d".into())?;
 
-        store.create_update_group("1".into(), HashSet::from([1, 3]), None, 1)?;
-        store.create_update_group("1".into(), HashSet::from([3, 2]), None, 3)?;
+        store.create_update_group("1".into(), HashSet::from([1, 3]), false, None, 1)?;
+        store.create_update_group("1".into(), HashSet::from([3, 2]), false, None, 3)?;
 
         // make sure the sender/recipients are validated
         assert!(matches!(
@@ -705,7 +2742,7 @@ mod tests {
 
         assert!(matches!(
             &messages_d_b[..],
-            [(2, Message { sender: 1, recipient: MessageRecipient::User(3), message, .. })] if &*message == "aaa"
+            [(2, Message { sender: 1, recipient: MessageRecipient::User(3), message, .. })] if message == "aaa"
         ));
 
         // users not in a group can't read the group messages
@@ -727,7 +2764,16 @@ mod tests {
         let group_messages_c = store.get_group_messages(2, 1)?;
         let group_messages_d = store.get_group_messages(3, 1)?;
         assert_eq!(group_messages_c, group_messages_d);
-        
+
+        // the last message is the newest one, whoever sent it
+        assert_eq!(store.get_last_message(0, MessageRecipient::User(1), None)?.map(|m| m.0), Some(1));
+        assert_eq!(store.get_last_message(0, MessageRecipient::User(1), Some(0))?.map(|m| m.0), Some(0));
+        assert!(store.get_last_message(0, MessageRecipient::User(2), None)?.is_none());
+        let (id, _) = store.send_message("eee".into(), 2, MessageRecipient::Group(1))?;
+        assert_eq!(store.get_last_message(3, MessageRecipient::Group(1), None)?.map(|m| m.0), Some(id));
+        assert_eq!(store.get_last_message(3, MessageRecipient::Group(1), Some(3))?.map(|m| m.0), Some(4));
+        assert!(matches!(store.get_last_message(1, MessageRecipient::Group(1), None), Err(StoreError::PermissionDenied)));
+
         Ok(())
     }
 
@@ -753,17 +2799,31 @@ mod tests {
 
         // users that aren't in a group can't delete it
         assert!(matches!(
-            store.delete_group(0, 2),
+            store.delete_group(0, 2, false),
             Err(StoreError::PermissionDenied)
         ));
-        store.delete_group(0, 1)?;
+        store.delete_group(0, 1, false)?;
 
         assert_message_count(&store, 5)?;
 
+        // regular members can't delete it either
+        assert!(matches!(
+            store.delete_group(1, 2, false),
+            Err(StoreError::InsufficientRole)
+        ));
+
         // delete the group with two messages
-        store.delete_group(1, 2)?;
+        store.delete_group(1, 3, false)?;
         assert_message_count(&store, 3)?;
 
+        // server admins can delete groups they aren't in
+        let (id, ..) = store.create_update_group("admin".into(), HashSet::from([1, 2]), false, None, 1)?;
+        assert!(store.get_all_groups()?.contains_key(&id));
+        store.delete_group(id, 0, true)?;
+        assert!(store.get_all_groups()?.is_empty());
+
+        assert_eq!(store.get_stats()?, StoreStats { users: 4, groups: 0, messages: 3, invites: 0, avatars: 0 });
+
         Ok(())
     }
 
@@ -773,20 +2833,666 @@ mod tests {
 
         assert_message_count(&store, 5)?;
         assert!(matches!(
-            store.delete_message(5, 0),
+            store.delete_message(5, 0, false),
             Err(StoreError::InvalidMessageId)
         ));
         assert!(matches!(
-            store.delete_message(4, 0),
+            store.delete_message(4, 0, false),
             Err(StoreError::PermissionDenied)
         ));
 
-        store.delete_message(4, 2)?;
-        store.delete_message(1, 0)?;
-        store.delete_message(2, 1)?;
+        store.delete_message(4, 2, false)?;
+        store.delete_message(1, 0, false)?;
+        store.delete_message(2, 1, false)?;
 
         assert_message_count(&store, 2)?;
 
+        // server admins can delete messages they weren't part of
+        store.delete_message(3, 0, true)?;
+
+        assert_message_count(&store, 1)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn forward_messages() -> Result {
+        let store = setup_messages_groups()?;
+
+        store.edit_message_tags(3, vec!["foo".into()], 2)?;
+
+        // message 3 is in group 1, which user 0 can't read
+        assert!(matches!(
+            store.forward_message(3, 0, MessageRecipient::User(1)),
+            Err(StoreError::PermissionDenied)
+        ));
+        assert!(matches!(
+            store.forward_message(5, 2, MessageRecipient::User(1)),
+            Err(StoreError::InvalidMessageId)
+        ));
+        // user 2 can read it but isn't in group 0
+        assert!(matches!(
+            store.forward_message(3, 2, MessageRecipient::Group(0)),
+            Err(StoreError::PermissionDenied)
+        ));
+
+        let (id, message) = store.forward_message(3, 2, MessageRecipient::User(0))?;
+        assert_eq!(id, 5);
+        assert!(matches!(
+            &message,
+            Message {
+                sender: 2,
+                recipient: MessageRecipient::User(0),
+                forwarded: Some(ForwardedFrom { sender: 2, .. }),
+                message,
+                tags,
+                ..
+            } if message == "bbb" && tags == &["foo"]
+        ));
+        assert_eq!(store.get_user_messages(0, 2)?, vec![(5, message)]);
+
+        // forwarding it again keeps the original sender
+        let (_, message) = store.forward_message(5, 0, MessageRecipient::User(1))?;
+        assert!(matches!(
+            message,
+            Message { sender: 0, forwarded: Some(ForwardedFrom { sender: 2, .. }), .. }
+        ));
+
+        assert_message_count(&store, 7)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn incremental_membership() -> Result {
+        let store = setup_messages_groups()?;
+
+        // group 1 is owned by 3, with 2 as a member
+        assert!(matches!(
+            store.add_group_members(1, HashSet::from([0]), 2),
+            Err(StoreError::InsufficientRole)
+        ));
+        assert!(matches!(
+            store.add_group_members(1, HashSet::from([4]), 3),
+            Err(StoreError::InvalidUserIds)
+        ));
+
+        let (updated, added) = store.add_group_members(1, HashSet::from([0, 2]), 3)?;
+        assert_eq!(added, vec![0]);
+        assert_eq!(updated.members.len(), 3);
+
+        assert!(matches!(
+            store.remove_group_members(1, HashSet::from([0]), 2),
+            Err(StoreError::InsufficientRole)
+        ));
+        let (updated, removed) = store.remove_group_members(1, HashSet::from([0, 1]), 3)?;
+        assert_eq!(removed, vec![0]);
+        assert_eq!(updated.members.len(), 2);
+
+        // the only owner can't leave while there are other members
+        assert!(matches!(store.leave_group(1, 3), Err(StoreError::LastOwner)));
+        assert!(matches!(store.leave_group(1, 0), Err(StoreError::PermissionDenied)));
+        assert_eq!(
+            store.leave_group(1, 2)?,
+            Some(group("1", [(3, GroupRole::Owner)]))
+        );
+
+        // the group (and its messages) is deleted when the last member leaves
+        assert_message_count(&store, 5)?;
+        assert_eq!(store.leave_group(1, 3)?, None);
+        assert_eq!(store.get_group(1)?, None);
+        assert_message_count(&store, 3)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn group_invites() -> Result {
+        let store = setup_messages_groups()?;
+
+        // only admins can manage invites
+        assert!(matches!(
+            store.create_group_invite(1, 2, None, None),
+            Err(StoreError::InsufficientRole)
+        ));
+        assert!(matches!(
+            store.create_group_invite(3, 3, None, None),
+            Err(StoreError::InvalidGroupId)
+        ));
+        assert!(matches!(
+            store.create_group_invite(1, 3, None, Some(0)),
+            Err(StoreError::InvalidMaxUses)
+        ));
+
+        let (token, _) = store.create_group_invite(1, 3, None, Some(2))?;
+        let (expired, _) = store.create_group_invite(1, 3, Some(0), None)?;
+        let (revoked, _) = store.create_group_invite(1, 3, None, None)?;
+
+        // expired invites aren't listed
+        let invites = store.get_group_invites(1, 3)?;
+        assert_eq!(invites.len(), 2);
+        assert!(invites.iter().all(|(t, _)| *t != expired));
+        assert!(matches!(store.get_group_invites(1, 2), Err(StoreError::InsufficientRole)));
+
+        assert!(matches!(store.revoke_group_invite(&revoked, 2), Err(StoreError::InsufficientRole)));
+        store.revoke_group_invite(&revoked, 3)?;
+        assert!(matches!(store.redeem_group_invite(&revoked, 0), Err(StoreError::InvalidInvite)));
+        assert!(matches!(store.redeem_group_invite(&expired, 0), Err(StoreError::InvalidInvite)));
+
+        // existing members don't use it up
+        let (_, _, added) = store.redeem_group_invite(&token, 2)?;
+        assert!(!added);
+        let (id, updated, added) = store.redeem_group_invite(&token, 0)?;
+        assert!(added);
+        assert_eq!(id, 1);
+        assert_eq!(updated.members.get(&0), Some(&GroupRole::Member));
+        assert!(matches!(
+            store.get_group_invites(1, 3)?.as_slice(),
+            [(_, GroupInvite { uses_left: Some(1), .. })]
+        ));
+
+        // the last use removes it
+        store.redeem_group_invite(&token, 1)?;
+        assert!(matches!(store.redeem_group_invite(&token, 1), Err(StoreError::InvalidInvite)));
+        assert_eq!(store.get_group_invites(1, 3)?, vec![]);
+
+        // deleting the group deletes its invites
+        let (token, _) = store.create_group_invite(1, 3, None, None)?;
+        store.delete_group(1, 3, false)?;
+        assert!(matches!(store.redeem_group_invite(&token, 1), Err(StoreError::InvalidInvite)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn public_channels() -> Result {
+        let store = setup_messages_groups()?;
+
+        assert_eq!(store.get_public_channels()?, HashMap::new());
+        assert!(matches!(store.join_public_channel(1, 0), Err(StoreError::PermissionDenied)));
+
+        // only admins can publish a group
+        assert!(matches!(
+            store.set_group_visibility(1, true, 2),
+            Err(StoreError::InsufficientRole)
+        ));
+        let channel = store.set_group_visibility(1, true, 3)?;
+        assert_eq!(store.get_public_channels()?, HashMap::from([(1, channel)]));
+
+        // anyone can join and then use the group like normal
+        let (channel, added) = store.join_public_channel(1, 0)?;
+        assert!(added);
+        assert_eq!(channel.members.get(&0), Some(&GroupRole::Member));
+        assert!(!store.join_public_channel(1, 0)?.1);
+        assert_eq!(store.get_group_messages(0, 1)?.len(), 2);
+        store.send_message("ddd".into(), 0, MessageRecipient::Group(1))?;
+
+        // making it private again doesn't kick anyone out
+        store.set_group_visibility(1, false, 3)?;
+        assert_eq!(store.get_public_channels()?, HashMap::new());
+        assert_eq!(store.get_group_messages(0, 1)?.len(), 3);
+        assert!(matches!(store.join_public_channel(1, 1), Err(StoreError::PermissionDenied)));
+
+        // groups can also start out public
+        let (id, channel, ..) = store.create_update_group("news".into(), HashSet::new(), true, None, 2)?;
+        assert!(channel.public);
+        assert_eq!(store.get_public_channels()?, HashMap::from([(id, channel)]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn manage_users() -> Result {
+        let store = setup_messages_groups()?;
+
+        // renaming keeps both directions in sync
+        assert!(matches!(store.rename_user(0, "b".into(), 3), Err(StoreError::UsernameInUse)));
+        assert!(matches!(store.rename_user(4, "e".into(), 3), Err(StoreError::InvalidUserIds)));
+        store.rename_user(0, "e".into(), 3)?;
+        assert_eq!(store.get_username_for_id(0)?.as_deref(), Some("e"));
+        assert_eq!(store.get_id_for_username("e")?, Some(0));
+        assert_eq!(store.get_id_for_username("a")?, None);
+
+        assert_eq!(store.get_user_details(0)?, UserDetails::default());
+        store.set_user_deactivated(0, true, 3)?;
+        assert!(store.get_user_details(0)?.deactivated);
+        store.set_user_deactivated(0, false, 3)?;
+        assert!(!store.get_user_details(0)?.deactivated);
+        store.set_user_admin(0, true, 3)?;
+        assert!(store.get_user_details(0)?.admin);
+
+        // anonymize user 1: their messages stay
+        let changed = store.delete_user(1, false, 3)?;
+        assert_eq!(changed, vec![(0, Some(group("1", [(3, GroupRole::Owner)])))]);
+        assert_message_count(&store, 5)?;
+        assert_eq!(store.get_username_for_id(1)?.as_deref(), Some(DELETED_USERNAME));
+        assert_eq!(store.get_id_for_username("b")?, None);
+        assert!(store.get_user_details(1)?.deleted);
+        assert!(matches!(store.delete_user(1, false, 3), Err(StoreError::InvalidUserIds)));
+        assert!(matches!(store.rename_user(1, "b".into(), 3), Err(StoreError::InvalidUserIds)));
+
+        // ids aren't reused
+        assert_eq!(store.create_user("b".into())?, 4);
+
+        assert!(matches!(
+            store.send_message("foo".into(), 0, MessageRecipient::User(1)),
+            Err(StoreError::InvalidUserIds)
+        ));
+
+        // erase user 3: their direct messages go, group messages stay
+        let changed = store.delete_user(3, true, 3)?;
+        assert_eq!(changed.len(), 2);
+        assert!(changed.contains(&(0, None)));
+        // user 2 was only a member, but now owns the group
+        assert!(changed.contains(&(1, Some(group("1", [(2, GroupRole::Owner)])))));
+        assert_message_count(&store, 4)?;
+        assert_eq!(store.get_user_messages(1, 3)?, vec![]);
+        assert_eq!(store.get_group_messages(2, 1)?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_profiles() -> Result {
+        let store = setup_messages_groups()?;
+
+        let details = store.update_profile(0, Some("Alice".into()), Some("busy".into()), Some(("image/png", &[1, 2, 3])))?;
+        assert_eq!(details.display_name.as_deref(), Some("Alice"));
+        assert_eq!(details.status.as_deref(), Some("busy"));
+        assert!(details.avatar_updated.is_some());
+        assert_eq!(store.get_avatar(0)?, Some(("image/png".into(), vec![1, 2, 3])));
+
+        // unspecified fields don't change, empty ones are cleared
+        let details = store.update_profile(0, None, Some("".into()), None)?;
+        assert_eq!(details.display_name.as_deref(), Some("Alice"));
+        assert_eq!(details.status, None);
+        assert_eq!(store.list_user_details()?.get(&0), Some(&details));
+
+        store.update_profile(0, None, None, Some(("", &[])))?;
+        assert_eq!(store.get_avatar(0)?, None);
+        assert_eq!(store.get_user_details(0)?.avatar_updated, None);
+
+        // deleting a user deletes their profile
+        store.update_profile(1, Some("Bob".into()), None, Some(("image/png", &[1])))?;
+        store.delete_user(1, false, 3)?;
+        assert_eq!(store.get_user_details(1)?.display_name, None);
+        assert_eq!(store.get_avatar(1)?, None);
+        assert!(matches!(
+            store.update_profile(1, Some("Bob".into()), None, None),
+            Err(StoreError::InvalidUserIds)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn audit_log() -> Result {
+        let store = Store::init::<PathBuf>(None)?;
+
+        assert_eq!(store.get_audit_log(None, None)?, vec![]);
+
+        let events = [
+            AuditEvent::UserCreated { user: 0 },
+            AuditEvent::Login { user: 0, remote_addr: "127.0.0.1:1234".into() },
+            AuditEvent::AdminAction { admin: 0, action: "deleted user 1".into() }
+        ];
+        for event in &events {
+            store.record_audit(event.clone())?;
+        }
+
+        // entries come back in the order they were recorded
+        let entries = store.get_audit_log(None, None)?;
+        assert_eq!(entries.iter().map(|e| e.event.clone()).collect::<Vec<_>>(), events);
+
+        // filter by time
+        let time = entries[0].time;
+        assert_eq!(store.get_audit_log(Some(time), Some(entries[2].time))?, entries);
+        assert_eq!(store.get_audit_log(Some(entries[2].time + 1), None)?, vec![]);
+        assert_eq!(store.get_audit_log(None, Some(time - 1))?, vec![]);
+        assert_eq!(store.get_audit_log(Some(time + 1), Some(time - 1))?, vec![]);
+
+        // changes are recorded along with the change itself, and failed ones aren't recorded at all
+        let user = store.create_user("a".into())?;
+        let (group, ..) = store.create_update_group("g".into(), HashSet::new(), true, None, user)?;
+        assert!(store.leave_group(group, 5).is_err());
+        let joined = store.create_user("b".into())?;
+        store.join_public_channel(group, joined)?;
+        store.leave_group(group, joined)?;
+        store.delete_user(user, false, 0)?;
+        let recorded: Vec<_> = store.get_audit_log(None, None)?.into_iter().skip(events.len()).map(|e| e.event).collect();
+        assert_eq!(recorded, [
+            AuditEvent::UserCreated { user },
+            AuditEvent::GroupCreated { group, by: user },
+            AuditEvent::UserCreated { user: joined },
+            AuditEvent::GroupEdited { group, by: joined, change: "joined".into() },
+            AuditEvent::GroupEdited { group, by: joined, change: "left".into() },
+            AuditEvent::GroupDeleted { group, by: 0 },
+            AuditEvent::AdminAction { admin: 0, action: format!("deleted user {user}") },
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_and_mutes() -> Result {
+        let store = setup_messages_groups()?;
+
+        // user 1 blocks user 0
+        assert!(matches!(store.set_user_blocked(1, 1, true), Err(StoreError::InvalidUserIds)));
+        store.set_user_blocked(1, 0, true)?;
+        assert_eq!(store.get_blocked_users(1)?, HashSet::from([0]));
+        assert!(store.get_blocked_users(0)?.is_empty());
+
+        // user 0 can't message them anymore, but they can still message user 0
+        assert!(matches!(
+            store.send_message("hi".into(), 0, MessageRecipient::User(1)),
+            Err(StoreError::Blocked)
+        ));
+        store.send_message("hi".into(), 1, MessageRecipient::User(0))?;
+
+        store.set_user_blocked(1, 0, false)?;
+        assert!(store.get_blocked_users(1)?.is_empty());
+        store.send_message("hi".into(), 0, MessageRecipient::User(1))?;
+
+        // users can only mute groups they're in
+        assert!(matches!(
+            store.set_conversation_muted(0, MessageRecipient::Group(0), true),
+            Err(StoreError::PermissionDenied)
+        ));
+        store.set_conversation_muted(1, MessageRecipient::Group(0), true)?;
+        store.set_conversation_muted(1, MessageRecipient::User(0), true)?;
+        assert_eq!(
+            store.get_muted_conversations(1)?,
+            vec![MessageRecipient::User(0), MessageRecipient::Group(0)]
+        );
+
+        assert_eq!(store.get_muting_users(3, MessageRecipient::Group(0))?, HashSet::from([1]));
+        // only messages from user 0 to user 1 are muted
+        assert_eq!(store.get_muting_users(0, MessageRecipient::User(1))?, HashSet::from([1]));
+        assert!(store.get_muting_users(1, MessageRecipient::User(0))?.is_empty());
+        assert!(store.get_muting_users(2, MessageRecipient::User(1))?.is_empty());
+
+        store.set_conversation_muted(1, MessageRecipient::Group(0), false)?;
+        assert!(store.get_muting_users(3, MessageRecipient::Group(0))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn push_subscriptions() -> Result {
+        let store = setup_messages_groups()?;
+
+        // the key is only generated once
+        let key = store.get_or_create_vapid_key(|| vec![1, 2, 3])?;
+        assert_eq!(key, vec![1, 2, 3]);
+        assert_eq!(store.get_or_create_vapid_key(|| unreachable!())?, key);
+
+        let subscription = |endpoint: &str| PushSubscription {
+            endpoint: endpoint.into(),
+            p256dh: "key".into(),
+            auth: "auth".into()
+        };
+        store.add_push_subscription(1, &subscription("https://a"))?;
+        store.add_push_subscription(1, &subscription("https://b"))?;
+        store.add_push_subscription(2, &subscription("https://c"))?;
+        assert!(matches!(store.add_push_subscription(7, &subscription("https://d")), Err(StoreError::InvalidUserIds)));
+
+        assert_eq!(store.get_push_subscriptions(1)?, vec![subscription("https://a"), subscription("https://b")]);
+        assert_eq!(store.get_push_subscriptions(0)?, vec![]);
+
+        store.remove_push_subscription(1, "https://a")?;
+        assert_eq!(store.get_push_subscriptions(1)?, vec![subscription("https://b")]);
+
+        // deleted users lose their subscriptions
+        store.delete_user(2, false, 3)?;
+        assert_eq!(store.get_push_subscriptions(2)?, vec![]);
+        assert_eq!(store.get_push_subscriptions(1)?, vec![subscription("https://b")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn webhooks() -> Result {
+        let store = setup_messages_groups()?;
+        let conversation = |recipient| WebhookTrigger::Conversation(recipient);
+
+        // only group admins can add webhooks to a group
+        assert!(matches!(
+            store.create_webhook(2, "a".into(), conversation(MessageRecipient::Group(1)), false),
+            Err(StoreError::InsufficientRole)
+        ));
+        assert!(matches!(
+            store.create_webhook(0, "a".into(), conversation(MessageRecipient::Group(1)), false),
+            Err(StoreError::PermissionDenied)
+        ));
+        let (group_hook, _) = store.create_webhook(3, "a".into(), conversation(MessageRecipient::Group(1)), false)?;
+        let (dm_hook, _) = store.create_webhook(0, "b".into(), conversation(MessageRecipient::User(1)), false)?;
+        let (tag_hook, _) = store.create_webhook(0, "c".into(), WebhookTrigger::Tag("foo".into()), false)?;
+        let (global_hook, _) = store.create_webhook(1, "d".into(), WebhookTrigger::Tag("foo".into()), true)?;
+
+        let queued_for = |message: &Message| -> Result<Vec<u16>> {
+            store.queue_webhook_deliveries(message, "MessageSent", "{}")?;
+            let due = store.get_due_webhook_deliveries(i64::MAX)?;
+            for (id, _, _) in &due {
+                store.finish_webhook_delivery(*id)?;
+            }
+            Ok(due.into_iter().map(|(_, delivery, _)| delivery.webhook).collect())
+        };
+
+        let (_, message) = store.send_message("a".into(), 3, MessageRecipient::Group(1))?;
+        assert_eq!(queued_for(&message)?, vec![group_hook]);
+        // both directions of the direct conversation
+        let (_, message) = store.send_message("a".into(), 1, MessageRecipient::User(0))?;
+        assert_eq!(queued_for(&message)?, vec![dm_hook]);
+        let (_, message) = store.send_message("a".into(), 0, MessageRecipient::User(1))?;
+        assert_eq!(queued_for(&message)?, vec![dm_hook]);
+        let (_, message) = store.send_message("a".into(), 0, MessageRecipient::User(2))?;
+        assert!(queued_for(&message)?.is_empty());
+
+        // user 0 can't read group 1, so only the admin's tag webhook sees this
+        let (id, _) = store.send_message("a".into(), 3, MessageRecipient::Group(1))?;
+        let message = store.edit_message_tags(id, vec!["foo".into()], 3)?.unwrap();
+        assert_eq!(queued_for(&message)?, vec![group_hook, global_hook]);
+        let (id, _) = store.send_message("a".into(), 0, MessageRecipient::User(2))?;
+        let message = store.edit_message_tags(id, vec!["foo".into()], 0)?.unwrap();
+        assert_eq!(queued_for(&message)?, vec![tag_hook, global_hook]);
+        // which it stops seeing once its owner isn't an admin anymore
+        store.set_user_admin(1, false, 0)?;
+        assert_eq!(queued_for(&message)?, vec![tag_hook]);
+
+        assert_eq!(store.get_webhooks(Some(0))?.len(), 2);
+        assert_eq!(store.get_webhooks(None)?.len(), 4);
+
+        // deleting a webhook drops its pending deliveries
+        store.queue_webhook_deliveries(&message, "MessageSent", "{}")?;
+        assert!(matches!(store.delete_webhook(tag_hook, 1, false), Err(StoreError::PermissionDenied)));
+        store.delete_webhook(tag_hook, 0, false)?;
+        store.delete_webhook(global_hook, 0, true)?;
+        let action = format!("deleted webhook {global_hook} of user 1");
+        assert_eq!(store.get_audit_log(None, None)?.pop().unwrap().event, AuditEvent::AdminAction { admin: 0, action });
+        assert!(store.get_due_webhook_deliveries(i64::MAX)?.is_empty());
+        assert!(matches!(store.delete_webhook(tag_hook, 0, false), Err(StoreError::InvalidWebhookId)));
+
+        // webhooks go away once their owner can't read the conversation
+        let webhook_ids = || -> Result<Vec<u16>> {
+            Ok(store.get_webhooks(None)?.into_iter().map(|(id, _)| id).collect())
+        };
+        let pending_for = || -> Result<Vec<u16>> {
+            Ok(store.get_due_webhook_deliveries(i64::MAX)?.into_iter().map(|(_, delivery, _)| delivery.webhook).collect())
+        };
+        store.set_group_role(1, 2, GroupRole::Admin, 3)?;
+        store.create_webhook(2, "e".into(), conversation(MessageRecipient::Group(1)), false)?;
+        let (_, message) = store.send_message("a".into(), 3, MessageRecipient::Group(1))?;
+        store.queue_webhook_deliveries(&message, "MessageSent", "{}")?;
+        store.remove_group_members(1, HashSet::from([2]), 3)?;
+        assert_eq!(webhook_ids()?, vec![group_hook, dm_hook]);
+        assert_eq!(pending_for()?, vec![group_hook]);
+
+        store.delete_user(0, false, 1)?;
+        assert_eq!(webhook_ids()?, vec![group_hook]);
+        // the next group gets the same id, and shouldn't inherit anything
+        store.delete_group(1, 3, false)?;
+        assert!(webhook_ids()?.is_empty());
+        assert!(pending_for()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reminders() -> Result {
+        let store = setup_messages_groups()?;
+        let (message_id, _) = store.send_message("a".into(), 3, MessageRecipient::Group(1))?;
+
+        // only about messages they can read
+        assert!(matches!(store.create_reminder(0, message_id, 10), Err(StoreError::PermissionDenied)));
+        assert!(matches!(store.create_reminder(2, u16::MAX, 10), Err(StoreError::InvalidMessageId)));
+        let (later, _) = store.create_reminder(2, message_id, 20)?;
+        let (sooner, _) = store.create_reminder(2, message_id, 10)?;
+        let (other, _) = store.create_reminder(3, message_id, 10)?;
+
+        let reminders: Vec<_> = store.get_reminders(2)?.into_iter().map(|(id, _)| id).collect();
+        assert_eq!(reminders, vec![sooner, later]);
+        assert_eq!(store.next_reminder_time(0)?, Some(10));
+        assert_eq!(store.next_reminder_time(10)?, Some(20));
+        assert_eq!(store.next_reminder_time(20)?, None);
+
+        let due = store.take_due_reminders(15, &HashSet::from([2]))?;
+        assert!(matches!(&due[..], [(id, Reminder { user: 2, .. }, _)] if *id == sooner));
+        // taking them removes them
+        assert!(store.take_due_reminders(15, &HashSet::from([2]))?.is_empty());
+        assert_eq!(store.get_reminders(2)?.len(), 1);
+        // unless they couldn't be delivered
+        let (id, reminder, _) = due.into_iter().next().unwrap();
+        store.restore_reminders(vec![(id, reminder.clone())])?;
+        assert_eq!(store.get_reminders(2)?.first(), Some(&(sooner, reminder)));
+        assert_eq!(store.take_due_reminders(15, &HashSet::from([2]))?.len(), 1);
+
+        assert!(matches!(store.cancel_reminder(later, 3), Err(StoreError::PermissionDenied)));
+        store.cancel_reminder(later, 2)?;
+        assert!(matches!(store.cancel_reminder(later, 2), Err(StoreError::InvalidReminderId)));
+
+        // reminders go away with their message, instead of going off for the next one with its id
+        store.delete_message(message_id, 3, false)?;
+        assert!(store.get_reminders(3)?.iter().all(|(id, _)| *id != other));
+        let (reused, _) = store.send_message("c".into(), 1, MessageRecipient::User(3))?;
+        assert_eq!(reused, message_id);
+        assert!(store.take_due_reminders(15, &HashSet::from([3]))?.is_empty());
+
+        // as are ones about messages in groups they've since left
+        let (message_id, _) = store.send_message("b".into(), 3, MessageRecipient::Group(1))?;
+        let (left, _) = store.create_reminder(2, message_id, 10)?;
+        let (stayed, _) = store.create_reminder(3, message_id, 10)?;
+        store.leave_group(1, 2)?;
+        let due = store.take_due_reminders(15, &HashSet::from([2, 3]))?;
+        assert!(matches!(&due[..], [(id, _, _)] if *id == stayed));
+        assert!(store.get_reminders(2)?.iter().all(|(id, _)| *id != left));
+
+        // and with the group their message was in
+        store.create_reminder(3, message_id, 10)?;
+        store.delete_group(1, 3, false)?;
+        assert!(store.get_reminders(3)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn bots() -> Result {
+        let store = setup_messages_groups()?;
+
+        let (bot_id, bot) = store.create_bot(0, "bot".into())?;
+        assert!(store.get_user_details(bot_id)?.bot);
+        assert_eq!(store.get_bot_for_token(&bot.token)?, bot_id);
+        // bots can't make more bots
+        assert!(matches!(store.create_bot(bot_id, "bot2".into()), Err(StoreError::PermissionDenied)));
+        assert!(matches!(store.create_bot(1, "bot".into()), Err(StoreError::UsernameInUse)));
+
+        // bots can be added to groups and talk like anyone else
+        store.add_group_members(1, HashSet::from([bot_id]), 3)?;
+        store.send_message("hi".into(), bot_id, MessageRecipient::Group(1))?;
+
+        assert_eq!(store.get_bots(Some(0))?, vec![(bot_id, bot.clone())]);
+        assert!(store.get_bots(Some(1))?.is_empty());
+
+        // resetting the token revokes the old one
+        assert!(matches!(store.reset_bot_token(bot_id, 1, false), Err(StoreError::PermissionDenied)));
+        let reset = store.reset_bot_token(bot_id, 1, true)?;
+        let action = format!("reset the token of bot {bot_id} of user 0");
+        assert_eq!(store.get_audit_log(None, None)?.pop().unwrap().event, AuditEvent::AdminAction { admin: 1, action });
+        assert_ne!(reset.token, bot.token);
+        assert!(matches!(store.get_bot_for_token(&bot.token), Err(StoreError::InvalidBotToken)));
+        assert_eq!(store.get_bot_for_token(&reset.token)?, bot_id);
+
+        // as does deleting the bot. that's only an admin action when it isn't their own
+        assert!(matches!(store.delete_bot(bot_id, 1, false), Err(StoreError::PermissionDenied)));
+        assert!(matches!(store.delete_bot(1, 1, true), Err(StoreError::InvalidUserIds)));
+        let logged = store.get_audit_log(None, None)?.len();
+        store.delete_bot(bot_id, 0, false)?;
+        let events: Vec<_> = store.get_audit_log(None, None)?.into_iter().skip(logged).map(|e| e.event).collect();
+        assert!(!events.iter().any(|event| matches!(event, AuditEvent::AdminAction { .. })));
+        assert!(matches!(store.get_bot_for_token(&reset.token), Err(StoreError::InvalidBotToken)));
+        assert!(store.get_bots(None)?.is_empty());
+
+        // or its owner, which also deactivates it
+        let (bot_id, bot) = store.create_bot(2, "bot2".into())?;
+        store.delete_user(2, false, 1)?;
+        assert!(matches!(store.get_bot_for_token(&bot.token), Err(StoreError::InvalidBotToken)));
+        assert!(store.get_bots(None)?.is_empty());
+        assert!(store.get_user_details(bot_id)?.deactivated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn incoming_webhooks() -> Result {
+        let store = setup_messages_groups()?;
+
+        // only group admins can add them to a group, and direct ones only post to their creator
+        assert!(matches!(
+            store.create_incoming_webhook(2, MessageRecipient::Group(1), "bot".into()),
+            Err(StoreError::InsufficientRole)
+        ));
+        assert!(matches!(
+            store.create_incoming_webhook(0, MessageRecipient::User(1), "bot".into()),
+            Err(StoreError::PermissionDenied)
+        ));
+        let (group_token, group_hook, group) = store.create_incoming_webhook(3, MessageRecipient::Group(1), "bot".into())?;
+        assert!(group.unwrap().members.contains_key(&group_hook.bot));
+        assert!(store.get_user_details(group_hook.bot)?.bot);
+        // bot names are usernames
+        assert!(matches!(
+            store.create_incoming_webhook(0, MessageRecipient::User(0), "bot".into()),
+            Err(StoreError::UsernameInUse)
+        ));
+        let (dm_token, dm_hook, group) = store.create_incoming_webhook(0, MessageRecipient::User(0), "bot2".into())?;
+        assert!(group.is_none());
+
+        let (_, message) = store.post_incoming_webhook(&group_token, "hi".into())?;
+        assert_eq!(message.sender, group_hook.bot);
+        assert_eq!(message.recipient, MessageRecipient::Group(1));
+        let (_, message) = store.post_incoming_webhook(&dm_token, "hi".into())?;
+        assert_eq!(message.sender, dm_hook.bot);
+        assert_eq!(message.recipient, MessageRecipient::User(0));
+
+        assert_eq!(store.get_incoming_webhooks(Some(0))?.len(), 1);
+        assert_eq!(store.get_incoming_webhooks(None)?.len(), 2);
+
+        // revoking the token stops posting
+        assert!(matches!(store.delete_incoming_webhook(dm_hook.bot, 1, false), Err(StoreError::PermissionDenied)));
+        store.delete_incoming_webhook(dm_hook.bot, 0, false)?;
+        assert!(matches!(store.delete_incoming_webhook(dm_hook.bot, 0, false), Err(StoreError::InvalidWebhookId)));
+        assert!(matches!(store.post_incoming_webhook(&dm_token, "hi".into()), Err(StoreError::InvalidWebhookId)));
+        assert!(matches!(store.post_incoming_webhook("nope", "hi".into()), Err(StoreError::InvalidWebhookId)));
+        // admins can revoke anyone's, which is logged
+        let (_, other_hook, _) = store.create_incoming_webhook(2, MessageRecipient::User(2), "bot3".into())?;
+        store.delete_incoming_webhook(other_hook.bot, 1, true)?;
+        let action = format!("deleted incoming webhook for bot {} of user 2", other_hook.bot);
+        assert_eq!(store.get_audit_log(None, None)?.pop().unwrap().event, AuditEvent::AdminAction { admin: 1, action });
+
+        // as does deleting the user that created it
+        store.delete_user(3, false, 1)?;
+        assert!(store.get_incoming_webhooks(None)?.is_empty());
+        assert!(matches!(store.post_incoming_webhook(&group_token, "hi".into()), Err(StoreError::InvalidWebhookId)));
+
         Ok(())
     }
 }
@@ -0,0 +1,86 @@
+//! Checking the URLs users ask the server to send requests to, so that push endpoints and webhooks
+//! can't be pointed at the server's own network
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use reqwest::Url;
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+        // carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+}
+
+fn is_private_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_private_ipv4(ip);
+    }
+    let first = ip.segments()[0];
+    ip.is_loopback() || ip.is_unspecified()
+        // unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// whether a host is `localhost` or an address on a private, loopback or link-local network
+///
+/// names are not resolved, so this only catches hosts that are written as addresses
+fn is_private_host(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host == "localhost" || host.ends_with(".localhost") {
+        return true;
+    }
+    // IPv6 hosts are in brackets
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(IpAddr::V4(ip)) => is_private_ipv4(ip),
+        Ok(IpAddr::V6(ip)) => is_private_ipv6(ip),
+        Err(_) => false
+    }
+}
+
+/// whether the server may send requests to `url`: it has to be https and must not point at a
+/// private host, unless `allow_private` is set
+pub fn is_allowed(url: &str, allow_private: bool) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    if allow_private {
+        return matches!(url.scheme(), "https" | "http") && url.host_str().is_some();
+    }
+    url.scheme() == "https" && url.host_str().is_some_and(|host| !is_private_host(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_allowed;
+
+    #[test]
+    fn request_targets() {
+        assert!(is_allowed("https://push.example.com/send/abc", false));
+        assert!(is_allowed("https://93.184.215.14/hook", false));
+        assert!(is_allowed("https://[2606:2800:21f:cb07::1]/hook", false));
+
+        assert!(!is_allowed("http://push.example.com/send/abc", false));
+        assert!(!is_allowed("ftp://push.example.com", false));
+        assert!(!is_allowed("not a url", false));
+        assert!(!is_allowed("https://localhost:8080/hook", false));
+        assert!(!is_allowed("https://api.localhost./hook", false));
+        assert!(!is_allowed("https://127.0.0.1/hook", false));
+        assert!(!is_allowed("https://2130706433/hook", false));
+        assert!(!is_allowed("https://10.1.2.3/hook", false));
+        assert!(!is_allowed("https://192.168.1.20/hook", false));
+        assert!(!is_allowed("https://169.254.169.254/latest/meta-data", false));
+        assert!(!is_allowed("https://100.64.0.1/hook", false));
+        assert!(!is_allowed("https://0.0.0.0/hook", false));
+        assert!(!is_allowed("https://[::1]/hook", false));
+        assert!(!is_allowed("https://[fd00::1]/hook", false));
+        assert!(!is_allowed("https://[fe80::1]/hook", false));
+        assert!(!is_allowed("https://[::ffff:127.0.0.1]/hook", false));
+
+        assert!(is_allowed("http://127.0.0.1:8080/hook", true));
+        assert!(is_allowed("https://192.168.1.20/hook", true));
+        assert!(!is_allowed("ftp://127.0.0.1/hook", true));
+    }
+}
@@ -0,0 +1,197 @@
+//! Outgoing webhooks: signed JSON POSTs for message events, retried from a queue in the store
+
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{error, warn};
+use reqwest::{header::CONTENT_TYPE, redirect::Policy, Client};
+use sha2::Sha256;
+use tokio::{select, sync::Notify, task::spawn_blocking, time::sleep};
+
+use crate::store::{Store, Webhook, WebhookDelivery};
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the body, keyed with the webhook's secret
+pub const SIGNATURE_HEADER: &str = "X-STC-Signature";
+pub const EVENT_HEADER: &str = "X-STC-Event";
+
+/// deliveries are dropped after failing this many times
+const MAX_ATTEMPTS: u32 = 8;
+/// doubles after every failed attempt
+const RETRY_BASE_DELAY: i64 = 10;
+/// how often to check for retries that became due
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// sign a webhook body with its secret
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .fold(String::from("sha256="), |mut signature, byte| {
+            let _ = write!(signature, "{byte:02x}");
+            signature
+        })
+}
+
+pub struct WebhookQueue {
+    client: Client,
+    /// woken whenever new deliveries are queued
+    notify: Notify,
+}
+
+impl WebhookQueue {
+    pub fn new() -> Self {
+        // a redirect could point the request at an internal address that was never checked
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .redirect(Policy::none())
+            .build()
+            .expect("TLS backend is available");
+        Self { client, notify: Notify::new() }
+    }
+
+    /// tell the worker there are new deliveries
+    pub fn wake(&self) {
+        self.notify.notify_one();
+    }
+
+    async fn deliver(&self, webhook: &Webhook, delivery: &WebhookDelivery) -> Result<(), String> {
+        let response = self.client.post(&webhook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event)
+            .header(SIGNATURE_HEADER, sign(&webhook.secret, delivery.payload.as_bytes()))
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_redirection() {
+            Err(format!("webhook responded with {status}, redirects aren't followed"))
+        } else {
+            Err(format!("webhook responded with {status}"))
+        }
+    }
+
+    /// deliver everything that's due, and reschedule failures
+    async fn deliver_due(&self, store: &Arc<Store>) {
+        let now = Utc::now().timestamp();
+        let store_2 = store.clone();
+        let due = match spawn_blocking(move || store_2.get_due_webhook_deliveries(now)).await {
+            Ok(Ok(due)) => due,
+            Ok(Err(err)) => return error!("Could not load webhook deliveries: {err}"),
+            Err(err) => return error!("Could not load webhook deliveries: {err}"),
+        };
+
+        for (id, delivery, webhook) in due {
+            let result = self.deliver(&webhook, &delivery).await;
+
+            let store = store.clone();
+            let finished = spawn_blocking(move || match result {
+                Ok(()) => store.finish_webhook_delivery(id),
+                Err(err) if delivery.attempts + 1 >= MAX_ATTEMPTS => {
+                    warn!("Giving up on webhook {} delivery: {err}", delivery.webhook);
+                    store.finish_webhook_delivery(id)
+                },
+                Err(err) => {
+                    warn!("Webhook {} delivery failed: {err}", delivery.webhook);
+                    let delay = RETRY_BASE_DELAY << delivery.attempts;
+                    store.retry_webhook_delivery(id, Utc::now().timestamp() + delay)
+                }
+            }).await;
+            match finished {
+                Ok(Ok(())) => {},
+                Ok(Err(err)) => error!("Could not update webhook delivery: {err}"),
+                Err(err) => error!("Could not update webhook delivery: {err}"),
+            }
+        }
+    }
+
+    /// deliver queued webhooks forever. anything left over from before a restart is picked up immediately
+    pub async fn run(&self, store: Arc<Store>) {
+        loop {
+            self.deliver_due(&store).await;
+            select! {
+                _ = self.notify.notified() => {},
+                _ = sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::{Arc, Mutex}};
+
+    use axum::{body::Bytes, extract::State, http::{header::LOCATION, HeaderMap, StatusCode}, response::IntoResponse, routing::post, Router};
+    use tokio::net::TcpListener;
+
+    use super::{sign, WebhookQueue, EVENT_HEADER, SIGNATURE_HEADER};
+    use crate::store::{MessageRecipient, Store, WebhookTrigger};
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    #[tokio::test]
+    async fn deliver_webhooks() {
+        // redirects the first request back to itself, then accepts everything
+        let received = Received::default();
+        let app = Router::new()
+            .route("/hook", post(|State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                let mut received = received.lock().unwrap();
+                received.push((headers, body));
+                if received.len() == 1 {
+                    (StatusCode::TEMPORARY_REDIRECT, [(LOCATION, "/hook")]).into_response()
+                } else {
+                    StatusCode::OK.into_response()
+                }
+            }))
+            .with_state(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let store = Arc::new(Store::init::<PathBuf>(None).unwrap());
+        store.create_user("a".into()).unwrap();
+        store.create_user("b".into()).unwrap();
+        let (_, webhook) = store.create_webhook(0, format!("http://{addr}/hook"), WebhookTrigger::Conversation(MessageRecipient::User(1)), false).unwrap();
+
+        let (_, message) = store.send_message("hi".into(), 1, MessageRecipient::User(0)).unwrap();
+        assert_eq!(store.queue_webhook_deliveries(&message, "MessageSent", r#"{"hello":1}"#).unwrap(), 1);
+
+        let queue = WebhookQueue::new();
+        queue.deliver_due(&store).await;
+        // the redirect isn't followed
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        // the failed delivery stays queued for later
+        let now = chrono::Utc::now().timestamp();
+        assert!(store.get_due_webhook_deliveries(now).unwrap().is_empty());
+        let (id, delivery, _) = store.get_due_webhook_deliveries(now + 60).unwrap().pop().unwrap();
+        assert_eq!(delivery.attempts, 1);
+
+        // pretend it's time to retry
+        store.retry_webhook_delivery(id, now).unwrap();
+        queue.deliver_due(&store).await;
+        assert!(store.get_due_webhook_deliveries(now + 3600).unwrap().is_empty());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        assert_eq!(body.as_ref(), br#"{"hello":1}"#);
+        assert_eq!(headers[EVENT_HEADER], "MessageSent");
+        assert_eq!(headers[SIGNATURE_HEADER], sign(&webhook.secret, body));
+    }
+
+    #[test]
+    fn signature() {
+        // from RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
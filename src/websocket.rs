@@ -1,33 +1,414 @@
-use std::{collections::{HashMap, HashSet}, fmt::Display, path::Path, sync::{Arc, RwLock}};
+use std::{collections::{HashMap, HashSet}, fmt::Display, future::Future, sync::{Arc, Mutex, RwLock}, time::{Duration, Instant}};
 
-use axum::extract::ws::{self, WebSocket};
+use axum::{extract::ws::{self, close_code, CloseFrame, WebSocket}, http::StatusCode};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
 use futures_util::StreamExt;
 use log::{error, warn};
 use rmp_serde::Serializer;
 use serde::{Deserialize, Serialize};
-use tokio::{select, sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender, error::SendError}, task::{spawn_blocking, JoinError}};
+use tokio::{select, sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender, error::SendError}, Notify}, task::{spawn_blocking, JoinError}, time::{sleep, timeout}};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
-use crate::store::{self, Message, MessageRecipient, Store, StoreError};
+use crate::{config::{Config, Features, Limits, RateLimit, RuntimeConfig}, commands::{self, BotCommand, CommandContext, CommandError, CommandOutput, CommandRegistry}, push::{PushError, PushSender, VapidKey}, targets, webhooks::WebhookQueue, store::{self, AuditEntry, AuditEvent, Bot, Group, GroupInvite, GroupRole, IncomingWebhook, Message, MessageRecipient, PushSubscription, Reminder, Store, StoreError, StoreStats, UserDetails, Webhook, WebhookTrigger}};
 
 pub struct WsState {
-    store: Store,
-    users: RwLock<HashMap<u16, UnboundedSender<ServerMessage>>>
+    store: Arc<Store>,
+    users: RwLock<HashMap<u16, UnboundedSender<ServerMessage>>>,
+    /// origins, admins, and other settings reloaded on SIGHUP
+    runtime: RwLock<Arc<RuntimeConfig>>,
+    pub limits: Limits,
+    features: Features,
+    allow_private_webhooks: bool,
+    /// only set up if a VAPID subject is configured
+    push: Option<PushSender>,
+    webhooks: Arc<WebhookQueue>,
+    /// rate limits for posting to incoming webhooks, by token
+    incoming_rate_limits: Mutex<HashMap<String, RateLimiter>>,
+    /// slash commands, built in and registered by bots
+    pub commands: CommandRegistry,
+    /// woken whenever a reminder is created
+    reminders: Notify,
+    /// cancelled when the server starts shutting down
+    shutdown: CancellationToken,
+    /// websocket sessions and background work that writes to the store
+    tasks: TaskTracker
+}
+
+/// the body of an outgoing webhook delivery
+#[derive(Serialize, Debug)]
+struct WebhookPayload<'a> {
+    event: &'static str,
+    message: &'a MessageWithId
+}
+
+/// how long clients should wait before reconnecting after a shutdown
+const RECONNECT_AFTER: u32 = 5;
+
+/// how often to check for reminders when none are coming up
+const REMINDER_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// the longest message text included in a push notification
+const MAX_PUSH_BODY_LENGTH: usize = 500;
+
+/// what the service worker gets for a new message
+#[derive(Serialize, Debug)]
+struct PushNotification {
+    id: u16,
+    recipient: MessageRecipient,
+    title: String,
+    body: String
 }
 
 impl WsState {
-    pub fn new<T: AsRef<Path>>(store_path: Option<T>) -> store::Result<Self> {
-        let store = Store::init(store_path)?;
+    pub fn new(config: &Config, shutdown: CancellationToken) -> store::Result<Self> {
+        let store = Store::init(config.store_path.as_ref())?;
+
+        let push = if let Some(subject) = config.vapid_subject.clone() {
+            // the key has to stay the same, or existing subscriptions stop working
+            let key = store.get_or_create_vapid_key(|| VapidKey::generate().to_bytes())?;
+            match VapidKey::from_bytes(&key) {
+                Ok(key) => Some(PushSender::new(key, subject)),
+                Err(err) => {
+                    error!("Push notifications are disabled: {err}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let commands = CommandRegistry::new();
+        if !config.features.reminders {
+            commands.unregister("remind");
+        }
+
         Ok(Self {
-            store,
-            users: RwLock::new(HashMap::new())
+            store: Arc::new(store),
+            users: RwLock::new(HashMap::new()),
+            runtime: RwLock::new(Arc::new(config.runtime.clone())),
+            limits: config.limits,
+            features: config.features,
+            allow_private_webhooks: config.allow_private_webhooks,
+            push,
+            webhooks: Arc::new(WebhookQueue::new()),
+            incoming_rate_limits: Mutex::new(HashMap::new()),
+            commands,
+            reminders: Notify::new(),
+            shutdown,
+            tasks: TaskTracker::new()
         })
     }
+
+    /// the current reloadable settings
+    pub fn runtime(&self) -> Arc<RuntimeConfig> {
+        self.runtime.read().unwrap().clone()
+    }
+
+    /// swap in new reloadable settings. sessions that are already open keep their rate limit
+    /// and admin status
+    pub fn reload(&self, runtime: RuntimeConfig) {
+        *self.runtime.write().unwrap() = Arc::new(runtime);
+    }
+
+    /// deliver outgoing webhooks until the server stops
+    pub fn run_webhooks(&self) -> impl Future<Output = ()> {
+        // nothing gets queued when webhooks are off
+        let enabled = self.features.webhooks;
+        let store = self.store.clone();
+        let webhooks = self.webhooks.clone();
+        async move {
+            if enabled {
+                webhooks.run(store).await;
+            }
+        }
+    }
+
+    /// run a websocket session (or anything else) so shutdown waits for it
+    pub fn track<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        self.tasks.track_future(future)
+    }
+
+    /// tell every session the server is going away, then wait up to `timeout` for them and any
+    /// background tasks to finish what they're writing to the store
+    pub async fn shutdown(&self, shutdown_timeout: Duration) {
+        self.shutdown.cancel();
+        self.tasks.close();
+        if timeout(shutdown_timeout, self.tasks.wait()).await.is_err() {
+            warn!("{} sessions or tasks were still running at shutdown", self.tasks.len());
+        }
+    }
+
+    /// deliver reminders as they become due until the server stops
+    pub fn run_reminders(self: &Arc<Self>) -> impl Future<Output = ()> {
+        let state = self.clone();
+        async move {
+            if !state.features.reminders {
+                return;
+            }
+            loop {
+                let now = Utc::now().timestamp();
+                state.deliver_reminders(now, None).await;
+
+                let state_2 = state.clone();
+                let next = match spawn_blocking(move || state_2.store.next_reminder_time(now)).await {
+                    Ok(Ok(next)) => next,
+                    Ok(Err(err)) => { error!("Could not load reminders: {err}"); None },
+                    Err(err) => { error!("Could not load reminders: {err}"); None }
+                };
+                let delay = next
+                    .map(|next| Duration::from_secs(next.saturating_sub(now).unsigned_abs()))
+                    .map_or(REMINDER_POLL_INTERVAL, |delay| delay.min(REMINDER_POLL_INTERVAL));
+                select! {
+                    _ = state.reminders.notified() => {},
+                    _ = sleep(delay) => {}
+                }
+            }
+        }
+    }
+
+    /// send the reminders due at `now` (only `user_id`'s, if given) to users with a live session.
+    /// everyone else gets theirs when they next log in
+    async fn deliver_reminders(self: &Arc<Self>, now: i64, user_id: Option<u16>) {
+        let online: HashSet<u16> = match user_id {
+            Some(user_id) => HashSet::from([user_id]),
+            None => self.users.read().unwrap().keys().copied().collect()
+        };
+        let state = self.clone();
+        // the store hands each reminder out once, so a login and the timer can't both deliver it
+        let due = match spawn_blocking(move || state.store.take_due_reminders(now, &online)).await {
+            Ok(Ok(due)) => due,
+            Ok(Err(err)) => return error!("Could not load reminders: {err}"),
+            Err(err) => return error!("Could not load reminders: {err}")
+        };
+
+        // they could have disconnected since, in which case the reminder waits for their next login
+        let mut undelivered = vec![];
+        {
+            let users = self.users.read().unwrap();
+            for (id, reminder, message) in due {
+                let sent = users.get(&reminder.user)
+                    .is_some_and(|client| client.send(ServerMessage::Reminder { id, message: (reminder.message, message).into() }).is_ok());
+                if !sent {
+                    undelivered.push((id, reminder));
+                }
+            }
+        }
+        if !undelivered.is_empty() {
+            let state = self.clone();
+            match spawn_blocking(move || state.store.restore_reminders(undelivered)).await {
+                Ok(Ok(())) => {},
+                Ok(Err(err)) => error!("Could not restore undelivered reminders: {err}"),
+                Err(err) => error!("Could not restore undelivered reminders: {err}")
+            }
+        }
+    }
+
+    /// queue deliveries to the outgoing webhooks that match a message event
+    fn queue_webhooks(self: &Arc<Self>, event: &'static str, message: MessageWithId) {
+        if !self.features.webhooks {
+            return;
+        }
+        let state = self.clone();
+        self.tasks.spawn(async move {
+            let payload = serde_json::to_string(&WebhookPayload { event, message: &message })
+                .expect("payload can be serialized");
+            let state_2 = state.clone();
+            match spawn_blocking(move || state_2.store.queue_webhook_deliveries(&message.message, event, &payload)).await {
+                Ok(Ok(0)) => {},
+                Ok(Ok(_)) => state.webhooks.wake(),
+                Ok(Err(err)) => error!("Could not queue webhook deliveries: {err}"),
+                Err(err) => error!("Could not queue webhook deliveries: {err}")
+            }
+        });
+    }
+
+    /// tell the conversation and any webhooks watching it that a message's tags changed
+    async fn tags_edited(self: &Arc<Self>, id: u16, message: Message) -> Result<(), ServerError> {
+        self.queue_webhooks("MessageEdited", MessageWithId { id, message: message.clone() });
+        let server_message = ServerMessage::MessageTagsEdited { id, tags: message.tags };
+        self.send_to_recipient(server_message, message.recipient, message.sender).await
+    }
+
+    /// notify users without a live session about a new message using Web Push
+    fn push_message(self: &Arc<Self>, users: Vec<u16>, message: &MessageWithId) {
+        if self.push.is_none() || users.is_empty() {
+            return;
+        }
+
+        let state = self.clone();
+        let id = message.id;
+        let recipient = message.message.recipient;
+        let sender = message.message.sender;
+        let body: String = message.message.message.chars().take(MAX_PUSH_BODY_LENGTH).collect();
+        self.tasks.spawn(async move {
+            let state_2 = state.clone();
+            let prepared = spawn_blocking(move || {
+                // title it with the sender's name (and the group's)
+                let sender_name = state_2.store.get_user_details(sender)?.display_name
+                    .or_else(|| state_2.store.list_users().ok()?.remove(&sender))
+                    .unwrap_or_default();
+                let title = match recipient {
+                    MessageRecipient::Group(group_id) => {
+                        let group = state_2.store.get_group(group_id)?.ok_or(StoreError::InvalidGroupId)?;
+                        format!("{sender_name} in {}", group.name)
+                    },
+                    MessageRecipient::User(_) => sender_name
+                };
+
+                let mut subscriptions = vec![];
+                for user in users {
+                    subscriptions.extend(state_2.store.get_push_subscriptions(user)?.into_iter().map(|s| (user, s)));
+                }
+                store::Result::Ok((title, subscriptions))
+            }).await;
+
+            let (title, subscriptions) = match prepared {
+                Ok(Ok(prepared)) => prepared,
+                Ok(Err(err)) => return error!("Could not prepare push notification: {err}"),
+                Err(err) => return error!("Could not prepare push notification: {err}")
+            };
+            let Some(push) = &state.push else { return };
+            let payload = serde_json::to_vec(&PushNotification { id, recipient, title, body })
+                .expect("notification can be serialized");
+
+            for (user, subscription) in subscriptions {
+                match push.send(&subscription, &payload).await {
+                    Ok(()) => {},
+                    Err(PushError::Gone) => {
+                        // the browser unsubscribed, so stop trying
+                        let state = state.clone();
+                        let endpoint = subscription.endpoint;
+                        if let Ok(Err(err)) = spawn_blocking(move || state.store.remove_push_subscription(user, &endpoint)).await {
+                            error!("Could not remove push subscription: {err}");
+                        }
+                    },
+                    Err(err) => warn!("Could not send push notification to user {user}: {err}")
+                }
+            }
+        });
+    }
+
+    /// get a user's avatar as (content type, data)
+    pub fn get_avatar(&self, user_id: u16) -> store::Result<Option<(String, Vec<u8>)>> {
+        self.store.get_avatar(user_id)
+    }
+
+    /// send a message to all live sessions of the users in a conversation
+    ///
+    /// members that muted the conversation get new messages marked as muted
+    async fn send_to_recipient(self: &Arc<Self>, message: ServerMessage, recipient: MessageRecipient, sender: u16) -> Result<(), ServerError> {
+        let state = self.clone();
+        let is_new_message = matches!(message, ServerMessage::MessageSent { .. });
+        let (members, muting) = spawn_blocking(move || {
+            let members = match recipient {
+                MessageRecipient::User(user_id) => Some(HashSet::from([sender, user_id])),
+                MessageRecipient::Group(group_id) => state.store.get_group_members(group_id)?
+            };
+            let muting = if is_new_message {
+                state.store.get_muting_users(sender, recipient)?
+            } else {
+                HashSet::new()
+            };
+            store::Result::Ok((members, muting))
+        }).await??;
+
+        if let Some(members) = members {
+            let muted_message = match &message {
+                ServerMessage::MessageSent { message, .. } => ServerMessage::MessageSent { message: message.clone(), muted: true },
+                _ => message.clone()
+            };
+
+            // send the message to each user in the conversation
+            let mut offline = vec![];
+            {
+                let users = self.users.read().unwrap();
+                for member in members {
+                    if let Some(client) = users.get(&member) {
+                        if muting.contains(&member) {
+                            client.send(muted_message.clone())?;
+                        } else {
+                            client.send(message.clone())?;
+                        }
+                    } else if member != sender && !muting.contains(&member) {
+                        offline.push(member);
+                    }
+                }
+            }
+
+            // members without a live session get a push notification instead
+            if let ServerMessage::MessageSent { message, .. } = &message {
+                self.push_message(offline, message);
+            }
+        }
+        Ok(())
+    }
+
+    /// post a message through an incoming webhook, and deliver it like any other message
+    ///
+    /// returns the status to respond to the webhook request with
+    pub async fn post_incoming_webhook(self: &Arc<Self>, token: String, text: String) -> StatusCode {
+        if !self.features.webhooks {
+            return StatusCode::NOT_FOUND;
+        }
+        // each webhook gets the same rate limit as a websocket session
+        let allowed = self.incoming_rate_limits.lock().unwrap()
+            .entry(token.clone())
+            .or_insert_with(|| RateLimiter::new(self.runtime().rate_limit))
+            .allow(Instant::now());
+        if !allowed {
+            return StatusCode::TOO_MANY_REQUESTS;
+        }
+
+        let state = self.clone();
+        let token_2 = token.clone();
+        let result = async {
+            let message: MessageWithId = spawn_blocking(move || state.store.post_incoming_webhook(&token_2, text)).await??
+                .into();
+            let (recipient, sender) = (message.message.recipient, message.message.sender);
+            self.queue_webhooks("MessageSent", message.clone());
+            self.send_to_recipient(ServerMessage::MessageSent { message, muted: false }, recipient, sender).await
+        }.await;
+
+        match result {
+            Ok(()) => StatusCode::NO_CONTENT,
+            Err(ServerError::StoreError(StoreError::InvalidWebhookId)) => {
+                // don't keep a rate limit around for every token someone guesses
+                self.incoming_rate_limits.lock().unwrap().remove(&token);
+                StatusCode::NOT_FOUND
+            },
+            // the bot was removed from the group, or blocked
+            Err(ServerError::StoreError(StoreError::PermissionDenied | StoreError::Blocked | StoreError::InvalidUserIds)) => StatusCode::FORBIDDEN,
+            Err(err) => {
+                error!("Error while posting incoming webhook: {err}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// kick a user's live session, if they have one
+    fn disconnect(&self, user_id: u16, reason: &str) {
+        if let Some(client) = self.users.read().unwrap().get(&user_id) {
+            let _ = client.send(ServerMessage::Disconnected { reason: reason.into() });
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 enum ClientMessage<'a> {
     RequestUsername { username: &'a str },
+    /// log in as a bot with its API token
+    AuthenticateBot { token: &'a str },
+
+    UpdateProfile {
+        #[serde(borrow)]
+        display_name: Option<&'a str>,
+        #[serde(borrow)]
+        status: Option<&'a str>,
+        #[serde(borrow)]
+        avatar: Option<&'a [u8]>
+    },
 
     // Messages
     GetMessages { recipient: MessageRecipient },
@@ -35,11 +416,74 @@ enum ClientMessage<'a> {
     EditMessage { id: u16, new_message: &'a str },
     EditTags { id: u16, new_tags: Vec<String> },
     DeleteMessage { id: u16 },
+    ForwardMessage { id: u16, recipient: MessageRecipient },
+
+    // Slash commands
+    ListCommands,
+    /// bots only. the command lasts until the bot disconnects
+    RegisterCommand { name: &'a str, description: &'a str },
+
+    // Reminders
+    /// `time` is a unix timestamp (seconds)
+    CreateReminder { message: u16, time: i64 },
+    ListReminders,
+    CancelReminder { id: u64 },
 
     // Groups
-    CreateGroup { name: &'a str, members: Vec<u16> },
+    CreateGroup { name: &'a str, members: Vec<u16>, #[serde(default)] public: bool },
     EditGroup { id: u16, new_name: &'a str, new_members: Vec<u16> },
-    DeleteGroup { id: u16 }
+    SetGroupRole { id: u16, user: u16, role: GroupRole },
+    AddMembers { id: u16, members: Vec<u16> },
+    RemoveMembers { id: u16, members: Vec<u16> },
+    LeaveGroup { id: u16 },
+    DeleteGroup { id: u16 },
+
+    // Channels
+    SetGroupVisibility { id: u16, public: bool },
+    ListPublicChannels,
+    JoinChannel { id: u16 },
+
+    // Invites
+    CreateInvite { group: u16, expires_in: Option<i64>, max_uses: Option<u32> },
+    ListInvites { group: u16 },
+    RevokeInvite { token: &'a str },
+    RedeemInvite { token: &'a str },
+
+    // User management (admins only)
+    RenameUser { id: u16, new_name: &'a str },
+    SetUserDeactivated { id: u16, deactivated: bool },
+    DeleteUser { id: u16, erase_messages: bool },
+
+    // Server administration (admins only)
+    SetUserAdmin { id: u16, admin: bool },
+    ListAllGroups,
+    GetStats,
+    DisconnectUser { id: u16 },
+    GetAuditLog { from: Option<i64>, to: Option<i64> },
+
+    // Push notifications
+    RegisterPush { endpoint: &'a str, p256dh: &'a str, auth: &'a str },
+    UnregisterPush { endpoint: &'a str },
+
+    // Outgoing webhooks
+    CreateWebhook { url: &'a str, trigger: WebhookTrigger },
+    ListWebhooks,
+    DeleteWebhook { id: u16 },
+
+    // Incoming webhooks
+    CreateIncomingWebhook { recipient: MessageRecipient, bot_name: &'a str },
+    ListIncomingWebhooks,
+    DeleteIncomingWebhook { bot: u16 },
+
+    // Bots
+    CreateBot { name: &'a str },
+    ListBots,
+    ResetBotToken { id: u16 },
+    DeleteBot { id: u16 },
+
+    // Blocking and muting
+    SetUserBlocked { id: u16, blocked: bool },
+    SetConversationMuted { conversation: MessageRecipient, muted: bool }
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -47,23 +491,110 @@ enum ClientMessage<'a> {
 enum ServerMessage {
     Error { err: String },
 
-    Welcome { user_id: u16, users: Vec<ServerUser>, groups: Vec<ServerGroup> },
+    Welcome {
+        user_id: u16,
+        users: Vec<ServerUser>,
+        groups: Vec<ServerGroup>,
+        /// blocked users aren't included in `users`
+        blocked: Vec<ServerUser>,
+        muted: Vec<MessageRecipient>,
+        /// the `applicationServerKey` for push subscriptions, if push is enabled
+        vapid_public_key: Option<String>
+    },
+
+    // the session was closed by the server
+    Disconnected { reason: String },
 
     // a completely new user was added
     UserAdded { user: ServerUser },
+    UserRenamed { id: u16, name: String },
+    UserUpdated { user: ServerUser },
+    UserDeleted { id: u16 },
     // an existing user joined
     UserOnline { id: u16 },
     UserOffline { id: u16 },
         
     MessagesForRecipient { recipient: MessageRecipient, messages: Vec<MessageWithId> },
-    MessageSent { message: MessageWithId },
+    /// `muted` is set for recipients that muted this conversation, so they shouldn't be notified
+    MessageSent { message: MessageWithId, muted: bool },
     MessageEdited { id: u16, message: String },
     MessageTagsEdited { id: u16, tags: Vec<String> },
     MessageDeleted { id: u16 },
 
     GroupAdded { group: ServerGroup },
     GroupEdited { group: ServerGroup },
-    GroupDeleted { id: u16 }
+    GroupDeleted { id: u16 },
+    PublicChannels { channels: Vec<ServerGroup> },
+
+    InviteCreated { invite: InviteWithToken },
+    Invites { group: u16, invites: Vec<InviteWithToken> },
+    InviteRevoked { token: String },
+
+    AllGroups { groups: Vec<ServerGroup> },
+    Stats { stats: StoreStats, sessions: usize },
+    AuditLog { entries: Vec<AuditEntry> },
+
+    WebhookCreated { webhook: WebhookWithId },
+    Webhooks { webhooks: Vec<WebhookWithId> },
+    WebhookDeleted { id: u16 },
+
+    IncomingWebhookCreated { webhook: IncomingWebhookWithToken },
+    IncomingWebhooks { webhooks: Vec<IncomingWebhookWithToken> },
+    IncomingWebhookDeleted { bot: u16 },
+
+    /// a command's response that only the caller sees
+    CommandResponse { recipient: MessageRecipient, text: String },
+    Commands { commands: Vec<CommandInfo> },
+    /// sent to a bot when someone uses one of its commands
+    CommandInvoked { command: String, args: String, sender: u16, recipient: MessageRecipient },
+
+    ReminderCreated { reminder: ReminderWithId },
+    Reminders { reminders: Vec<ReminderWithId> },
+    ReminderCancelled { id: u64 },
+    /// a reminder came due
+    Reminder { id: u64, message: MessageWithId },
+
+    /// the server is going away. reconnect after this many seconds
+    ServerShutdown { reconnect_after: u32 },
+
+    BotCreated { bot: BotWithId },
+    Bots { bots: Vec<BotWithId> },
+    BotTokenReset { bot: BotWithId },
+
+    UserBlocked { id: u16, blocked: bool },
+    ConversationMuted { conversation: MessageRecipient, muted: bool }
+}
+
+/// make sure all characters are valid
+fn validate_username(username: &str) -> Result<(), ServerError> {
+    if !username.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(ServerError::InvalidUsername);
+    }
+    Ok(())
+}
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+const MAX_STATUS_LENGTH: usize = 140;
+
+/// free-form profile text can't contain control characters (newlines etc.)
+fn validate_profile_text(text: &str, max_length: usize, err: &'static str) -> Result<(), ServerError> {
+    if text.chars().count() > max_length || text.chars().any(char::is_control) {
+        return Err(ServerError::InvalidProfile(err));
+    }
+    Ok(())
+}
+
+/// determine the content type of an avatar image from its magic bytes
+fn avatar_content_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None
+    }
 }
 
 /// errors that get sent to the client
@@ -71,22 +602,45 @@ enum ServerMessage {
 enum ServerError {
     UsernameInUse,
     InvalidUsername, // invalid characters
+    AccountDeactivated,
+    BotAccount,
+    NotBot,
+    CommandError(CommandError),
+    NotAdmin,
+    InvalidProfile(&'static str),
+    InvalidPushSubscription,
+    InvalidWebhookUrl,
     SelfMessage,
+    InvalidExpiry,
+    RateLimited,
+    FeatureDisabled(&'static str),
     StoreError(StoreError),
     JoinError(JoinError),
-    SendError(SendError<ServerMessage>)
+    SendError(Box<SendError<ServerMessage>>)
 }
 
 impl From<StoreError> for ServerError { fn from(v: StoreError) -> Self { Self::StoreError(v) } }
+impl From<CommandError> for ServerError { fn from(v: CommandError) -> Self { Self::CommandError(v) } }
 impl From<JoinError> for ServerError { fn from (v: JoinError) -> Self { Self::JoinError(v) } }
-impl From<SendError<ServerMessage>> for ServerError { fn from (v: SendError<ServerMessage>) -> Self { Self::SendError(v) } }
+impl From<SendError<ServerMessage>> for ServerError { fn from (v: SendError<ServerMessage>) -> Self { Self::SendError(Box::new(v)) } }
 
 impl Display for ServerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::UsernameInUse => write!(f, "Someone has already logged in with that username"),
             Self::InvalidUsername => write!(f, "Username may only contain alphanumeric characters"),
+            Self::AccountDeactivated => write!(f, "This account has been deactivated"),
+            Self::NotAdmin => write!(f, "Only server admins can do that"),
+            Self::NotBot => write!(f, "Only bots can do that"),
+            Self::CommandError(err) => write!(f, "{err}"),
+            Self::BotAccount => write!(f, "Bot accounts can't log in with a username"),
+            Self::InvalidProfile(err) => write!(f, "Invalid profile: {err}"),
+            Self::InvalidPushSubscription => write!(f, "Invalid push subscription"),
+            Self::InvalidWebhookUrl => write!(f, "Webhook URLs must be https, and can't point at a private network"),
             Self::SelfMessage => write!(f, "You cannot send messages to yourself"),
+            Self::InvalidExpiry => write!(f, "Expiry must be in the future"),
+            Self::RateLimited => write!(f, "You're sending messages too quickly"),
+            Self::FeatureDisabled(feature) => write!(f, "{feature} are disabled on this server"),
             Self::JoinError(err) => write!(f, "Error while joining threads: {err}"),
             Self::StoreError(err) => write!(f, "Store error: {err}"),
             Self::SendError(err) => write!(f, "Error while sending message: {err}")
@@ -108,33 +662,196 @@ impl From<(u16, Message)> for MessageWithId {
     }
 }
 
+/// the secret is only sent once, when the webhook is created
+#[derive(Serialize, Debug, Clone)]
+struct WebhookWithId {
+    id: u16,
+    owner: u16,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    trigger: WebhookTrigger,
+    global: bool
+}
+
+impl From<(u16, Webhook)> for WebhookWithId {
+    fn from((id, webhook): (u16, Webhook)) -> Self {
+        let Webhook { owner, url, secret: _, trigger, global } = webhook;
+        Self { id, owner, url, secret: None, trigger, global }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct ReminderWithId {
+    id: u64,
+    #[serde(flatten)]
+    reminder: Reminder
+}
+
+impl From<(u64, Reminder)> for ReminderWithId {
+    fn from((id, reminder): (u64, Reminder)) -> Self {
+        Self { id, reminder }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct CommandInfo {
+    name: String,
+    description: String
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct BotWithId {
+    id: u16,
+    owner: u16,
+    /// only sent when the bot is created or its token is reset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>
+}
+
+impl From<(u16, Bot)> for BotWithId {
+    fn from((id, bot): (u16, Bot)) -> Self {
+        let Bot { owner, token: _ } = bot;
+        Self { id, owner, token: None }
+    }
+}
+
+/// posting to `/hooks/{token}` sends a message
+#[derive(Serialize, Debug, Clone)]
+struct IncomingWebhookWithToken {
+    /// only sent when the webhook is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(flatten)]
+    webhook: IncomingWebhook
+}
+
+impl From<IncomingWebhook> for IncomingWebhookWithToken {
+    fn from(webhook: IncomingWebhook) -> Self {
+        Self { token: None, webhook }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct InviteWithToken {
+    token: String,
+    #[serde(flatten)]
+    invite: GroupInvite
+}
+
+/// tokens are the keys in the DB
+impl From<(String, GroupInvite)> for InviteWithToken {
+    fn from((token, invite): (String, GroupInvite)) -> Self {
+        Self { token, invite }
+    }
+}
+
 #[derive(Serialize, Debug, Clone)]
 struct ServerUser {
     id: u16,
     name: String,
-    online: bool
+    online: bool,
+    display_name: Option<String>,
+    status: Option<String>,
+    /// when the avatar at `/avatar/{id}` was last changed, if there is one
+    avatar: Option<i64>,
+    bot: bool
+}
+
+impl ServerUser {
+    fn new(id: u16, name: String, details: Option<UserDetails>, online: bool) -> Self {
+        let details = details.unwrap_or_default();
+        Self {
+            id,
+            name,
+            online,
+            display_name: details.display_name,
+            status: details.status,
+            avatar: details.avatar_updated,
+            bot: details.bot
+        }
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
 struct ServerGroup {
     id: u16,
     name: String,
-    members: Vec<String>
+    members: Vec<String>,
+    admins: Vec<String>,
+    owners: Vec<String>,
+    public: bool
+}
+
+impl ServerGroup {
+    /// resolve the member usernames
+    fn new(id: u16, group: Group, users: &HashMap<u16, String>) -> Self {
+        let mut members = vec![];
+        let mut admins = vec![];
+        let mut owners = vec![];
+        for (member, role) in group.members {
+            if let Some(name) = users.get(&member) {
+                match role {
+                    GroupRole::Owner => owners.push(name.clone()),
+                    GroupRole::Admin => admins.push(name.clone()),
+                    GroupRole::Member => {}
+                }
+                members.push(name.clone());
+            }
+        }
+        Self { id, name: group.name, members, admins, owners, public: group.public }
+    }
+}
+
+/// a token bucket for the messages a client sends
+struct RateLimiter {
+    limit: RateLimit,
+    tokens: f64,
+    last: Instant
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        Self { limit, tokens: limit.burst.into(), last: Instant::now() }
+    }
+
+    /// take a token, if there is one
+    fn allow(&mut self, now: Instant) -> bool {
+        if self.limit.per_second == 0 {
+            return true;
+        }
+        let refill = now.duration_since(self.last).as_secs_f64() * f64::from(self.limit.per_second);
+        self.tokens = (self.tokens + refill).min(self.limit.burst.into());
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 pub struct WsHandler {
     socket: WebSocket,
     state: Arc<WsState>,
     user_id: Option<u16>,
+    is_admin: bool,
+    is_bot: bool,
+    /// where this connection came from, for the audit log
+    remote_addr: String,
+    rate_limiter: RateLimiter,
     channel: (UnboundedSender<ServerMessage>, UnboundedReceiver<ServerMessage>)
 }
 
 impl WsHandler {
-    pub fn new(socket: WebSocket, state: Arc<WsState>) -> Self {
+    pub fn new(socket: WebSocket, state: Arc<WsState>, remote_addr: String) -> Self {
         // setup the channel (but don't update the users map just yet)
         let channel = unbounded_channel::<ServerMessage>();
         
-        WsHandler { socket, state, channel, user_id: None }
+        // the limit in place when they connected applies for the whole session
+        let rate_limiter = RateLimiter::new(state.runtime().rate_limit);
+        WsHandler { socket, state, channel, user_id: None, is_admin: false, is_bot: false, remote_addr, rate_limiter }
     }
 
     /// send a ServerMessage to our client
@@ -163,31 +880,134 @@ impl WsHandler {
 
     /// send a broadcast message to all clients in the map that match the recipient
     async fn send_to_recipient(&mut self, message: ServerMessage, recipient: MessageRecipient, sender: u16) -> Result<(), ServerError> {
-        match recipient {
-            MessageRecipient::User(user_id) => {
-                let users = self.state.users.read().unwrap();
-                
-                // send it to the sender
-                if let Some(sender) = users.get(&sender) {
-                    sender.send(message.clone())?;
-                }
-                
-                // if the recipient is in the map, send to them
-                if let Some(recipient) = users.get(&user_id) {
-                    recipient.send(message)?;
-                }
+        self.state.send_to_recipient(message, recipient, sender).await
+    }
+
+    /// append an event that doesn't go with any change to the store to the audit log. changes are
+    /// recorded by the store, in the same transaction
+    ///
+    /// this doesn't borrow `self`, since the socket can't be shared between threads
+    fn audit(&self, event: AuditEvent) -> impl Future<Output = Result<(), ServerError>> {
+        let state = self.state.clone();
+        async move {
+            spawn_blocking(move || state.store.record_audit(event)).await??;
+            Ok(())
+        }
+    }
+
+    /// record an action taken by a server admin in the audit log
+    fn audit_admin(&self, action: String) -> impl Future<Output = Result<(), ServerError>> {
+        let admin = self.user_id.unwrap_or_default();
+        self.audit(AuditEvent::AdminAction { admin, action })
+    }
+
+    /// notify group members about a membership change
+    ///
+    /// added members see a new group, removed members see it deleted, and everyone else sees it edited
+    fn send_group_changes(&self, group: ServerGroup, added: &[u16], removed: &[u16], retained: &[u16]) -> Result<(), ServerError> {
+        let id = group.id;
+        let added_message = ServerMessage::GroupAdded { group: group.clone() };
+        let removed_message = ServerMessage::GroupDeleted { id };
+        let edited_message = ServerMessage::GroupEdited { group };
+
+        // broadcast the appropriate message to all members
+        let users = self.state.users.read().unwrap();
+
+        for member in added {
+            if let Some(client) = users.get(member) {
+                client.send(added_message.clone())?;
+            }
+        }
+        for member in removed {
+            if let Some(client) = users.get(member) {
+                client.send(removed_message.clone())?;
+            }
+        }
+        for member in retained {
+            if let Some(client) = users.get(member) {
+                client.send(edited_message.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// mark this session as logged in as `user_id` and send them everything they need to start
+    async fn finish_login(&mut self, user_id: u16, broadcast_message: ServerMessage, is_admin: bool) -> Result<(), ServerError> {
+        self.audit(AuditEvent::Login { user: user_id, remote_addr: self.remote_addr.clone() }).await?;
+
+        self.send_broadcast(broadcast_message);
+
+        self.state.users.write().unwrap().insert(user_id, self.channel.0.clone());
+        self.user_id = Some(user_id);
+        self.is_admin = is_admin;
+
+        // get existing users
+        let state = self.state.clone();
+        let (users, groups, blocked, muted) = spawn_blocking(move || -> Result<_, ServerError> {
+            let online_users = state.users.read().unwrap();
+            let users = state.store.list_users()?;
+            // list all  groups they belong to
+            let groups = state.store.get_groups_for_user(user_id)?.into_iter()
+                .map(|(id, group)| ServerGroup::new(id, group, &users))
+                .collect();
+            // turn these into ServerUsers
+            let details = state.store.list_user_details()?;
+            let blocked_ids = state.store.get_blocked_users(user_id)?;
+            let (blocked, users) = users.into_iter()
+                .filter(|(id, _)| *id != user_id)
+                .map(|(id, username)|
+                     ServerUser::new(id, username, details.get(&id).cloned(), online_users.contains_key(&id))
+                )
+                .partition(|user| blocked_ids.contains(&user.id));
+            let muted = state.store.get_muted_conversations(user_id)?;
+            Ok((users, groups, blocked, muted))
+        }).await??;
+
+        let vapid_public_key = self.state.push.as_ref().map(PushSender::public_key);
+        let welcome = ServerMessage::Welcome { user_id, users, groups, blocked, muted, vapid_public_key };
+        self.send_message(&welcome).await;
+
+        // anything that came due while they were away
+        let state = self.state.clone();
+        if state.features.reminders {
+            state.deliver_reminders(Utc::now().timestamp(), Some(user_id)).await;
+        }
+        Ok(())
+    }
+
+    /// store a new message and deliver it to the conversation
+    async fn send_new_message(&mut self, message: String, sender: u16, recipient: MessageRecipient) -> Result<(), ServerError> {
+        let state = self.state.clone();
+        let message: MessageWithId = spawn_blocking(move || state.store.send_message(message, sender, recipient)).await??
+            .into();
+        self.state.queue_webhooks("MessageSent", message.clone());
+        let server_message = ServerMessage::MessageSent { message, muted: false };
+        self.send_to_recipient(server_message, recipient, sender).await
+    }
+
+    /// run a slash command sent by this user
+    async fn run_command(&mut self, sender: u16, name: &str, args: &str, recipient: MessageRecipient) -> Result<(), ServerError> {
+        let online: HashSet<u16> = self.state.users.read().unwrap().keys().copied().collect();
+        let state = self.state.clone();
+        let (command, args) = (name.to_owned(), args.to_owned());
+        let (command, args, output) = spawn_blocking(move || {
+            let context = CommandContext { store: &state.store, sender, recipient, online: &online };
+            let output = state.commands.run(&command, &context, &args);
+            (command, args, output)
+        }).await?;
+
+        match output? {
+            CommandOutput::Send(message) => self.send_new_message(message, sender, recipient).await?,
+            CommandOutput::Ephemeral(text) => self.channel.0.send(ServerMessage::CommandResponse { recipient, text })?,
+            CommandOutput::TagsEdited(id, message) => self.state.tags_edited(id, message).await?,
+            CommandOutput::ReminderSet(id, reminder) => {
+                self.state.reminders.notify_one();
+                self.channel.0.send(ServerMessage::ReminderCreated { reminder: ReminderWithId { id, reminder } })?;
             },
-            MessageRecipient::Group(group_id) => {
-                let state = self.state.clone();
-                if let Some(members) = spawn_blocking(move || state.store.get_group_members(group_id)).await?? {
-                    // send the message to each user in the group
-                    let users = self.state.users.read().unwrap();
-                    for member in members {
-                        if let Some(client) = users.get(&member) {
-                            client.send(message.clone())?;
-                        }
-                    }
-                }
+            CommandOutput::Forward { bot } => {
+                let users = self.state.users.read().unwrap();
+                let client = users.get(&bot).ok_or(CommandError::BotOffline)?;
+                client.send(ServerMessage::CommandInvoked { command, args, sender, recipient })?;
             }
         }
         Ok(())
@@ -202,19 +1022,14 @@ impl WsHandler {
                     return Ok(());
                 }
 
-                // make sure all characters are valid
-                if !requested_username.chars()
-                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
-                {
-                    return Err(ServerError::InvalidUsername);
-                }
+                validate_username(requested_username)?;
 
                 let state = self.state.clone();
                 let username: String = requested_username.into();
 
                 // get a user id for this username
                 // (depends on whether this user already existed or not)
-                let (user_id, broadcast_message) = spawn_blocking(move || {
+                let (user_id, broadcast_message, admin) = spawn_blocking(move || {
                     let existing_user_id = state.store.get_id_for_username(&username)?;
                     if let Some(id) = existing_user_id {
                         {
@@ -226,57 +1041,95 @@ impl WsHandler {
                             }
                         }
 
-                        Ok((id, ServerMessage::UserOnline { id }))
+                        let details = state.store.get_user_details(id)?;
+                        if details.deactivated {
+                            return Err(ServerError::AccountDeactivated);
+                        }
+                        if details.bot {
+                            return Err(ServerError::BotAccount);
+                        }
+
+                        Ok((id, ServerMessage::UserOnline { id }, details.admin))
                     } else {
                         // create user
                         let id = state.store.create_user(username.clone())?;
 
-                        let user = ServerUser { id, name: username, online: true };
+                        let user = ServerUser::new(id, username, None, true);
 
-                        Ok((id, ServerMessage::UserAdded { user }))
+                        Ok((id, ServerMessage::UserAdded { user }, false))
                     }
                 }).await??;
 
-                self.send_broadcast(broadcast_message);
-
-                self.state.users.write().unwrap().insert(user_id, self.channel.0.clone());
-                self.user_id = Some(user_id);
+                // admins are either flagged in the store or listed in the config
+                let is_admin = admin || self.state.runtime().admins.contains(requested_username);
+                self.finish_login(user_id, broadcast_message, is_admin).await?;
+            },
+            ClientMessage::AuthenticateBot { token } => {
+                if !self.state.features.bots {
+                    return Err(ServerError::FeatureDisabled("Bots"));
+                }
+                // they can't do this if they're already initialized
+                if self.user_id.is_some() {
+                    warn!("User tried to re-initialize");
+                    return Ok(());
+                }
 
-                // get existing users
                 let state = self.state.clone();
-                let (users, groups) = spawn_blocking(move || -> Result<_, ServerError> {
-                    let online_users = state.users.read().unwrap();
-                    let users = state.store.list_users()?;
-                    // list all  groups they belong to
-                    let groups = state.store.get_groups_for_user(user_id)?.into_iter()
-                        .map(|(id, (name, members))| {
-                            ServerGroup {
-                                id,
-                                name,
-                                // resolve the member usernames
-                                members: members
-                                    .into_iter()
-                                    .filter_map(|id| users.get(&id).map(Into::into))
-                                    .collect()
-                            }
-                        })
-                        .collect();
-                    // turn these into ServerUsers
-                    let users = users.into_iter()
-                        .filter(|(id, _)| *id != user_id)
-                        .map(|(id, username)|
-                             ServerUser {
-                                 id,
-                                 name: username,
-                                 online: online_users.contains_key(&id)
-                             }
-                        )
-                        .collect();
-                    Ok((users, groups))
+                let token = token.to_owned();
+                let user_id = spawn_blocking(move || {
+                    let id = state.store.get_bot_for_token(&token)?;
+                    if state.users.read().unwrap().contains_key(&id) {
+                        return Err(ServerError::UsernameInUse);
+                    }
+                    if state.store.get_user_details(id)?.deactivated {
+                        return Err(ServerError::AccountDeactivated);
+                    }
+                    Ok(id)
                 }).await??;
 
-                let welcome = ServerMessage::Welcome { user_id, users, groups };
-                self.send_message(&welcome).await;
+                // bots are never admins
+                self.finish_login(user_id, ServerMessage::UserOnline { id: user_id }, false).await?;
+                self.is_bot = true;
+            },
+            ClientMessage::UpdateProfile { display_name, status, avatar } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let display_name = display_name.map(str::trim);
+                    if let Some(display_name) = display_name {
+                        validate_profile_text(display_name, MAX_DISPLAY_NAME_LENGTH, "display name is too long or contains invalid characters")?;
+                    }
+                    let status = status.map(str::trim);
+                    if let Some(status) = status {
+                        validate_profile_text(status, MAX_STATUS_LENGTH, "status is too long or contains invalid characters")?;
+                    }
+                    let avatar = match avatar {
+                        // an empty avatar removes it
+                        Some([]) => Some(("", vec![])),
+                        Some(data) if data.len() > self.state.limits.max_avatar_size => {
+                            return Err(ServerError::InvalidProfile("avatar is too large"));
+                        },
+                        Some(data) => {
+                            let content_type = avatar_content_type(data)
+                                .ok_or(ServerError::InvalidProfile("avatar must be a PNG, JPEG, GIF or WebP image"))?;
+                            Some((content_type, data.to_owned()))
+                        },
+                        None => None
+                    };
+
+                    let state = self.state.clone();
+                    let display_name = display_name.map(ToOwned::to_owned);
+                    let status = status.map(ToOwned::to_owned);
+                    let user = spawn_blocking(move || {
+                        let avatar = avatar.as_ref().map(|(content_type, data)| (*content_type, data.as_slice()));
+                        let details = state.store.update_profile(user_id, display_name, status, avatar)?;
+                        let name = state.store.list_users()?.remove(&user_id).unwrap_or_default();
+                        store::Result::Ok(ServerUser::new(user_id, name, Some(details), true))
+                    }).await??;
+
+                    self.send_broadcast(ServerMessage::UserUpdated { user });
+                } else {
+                    warn!("Uninitialized user");
+                }
             },
             ClientMessage::GetMessages { recipient } => {
                 // they can't do this if they haven't initialized
@@ -300,12 +1153,96 @@ impl WsHandler {
                         return Err(ServerError::SelfMessage);
                     }
                     
-                    let state = self.state.clone();
-                    let message = message.into();
-                    let message = spawn_blocking(move || state.store.send_message(message, id, recipient)).await??
-                        .into(); 
-                    let server_message = ServerMessage::MessageSent { message };
-                    self.send_to_recipient(server_message, recipient, id).await?;
+                    if let Some((name, args)) = self.state.commands.parse(message) {
+                        return self.run_command(id, name, args, recipient).await;
+                    }
+                    // `//` escapes a leading slash
+                    let message = if message.starts_with("//") { &message[1..] } else { message };
+                    self.send_new_message(message.into(), id, recipient).await?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::CreateReminder { message, time } => {
+                if !self.state.features.reminders {
+                    return Err(ServerError::FeatureDisabled("Reminders"));
+                }
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let reminder = spawn_blocking(move || state.store.create_reminder(user_id, message, time)).await??;
+                    self.state.reminders.notify_one();
+                    self.channel.0.send(ServerMessage::ReminderCreated { reminder: reminder.into() })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListReminders => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let reminders = spawn_blocking(move || state.store.get_reminders(user_id)).await??
+                        .into_iter()
+                        .map(ReminderWithId::from)
+                        .collect();
+                    self.channel.0.send(ServerMessage::Reminders { reminders })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::CancelReminder { id } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    spawn_blocking(move || state.store.cancel_reminder(id, user_id)).await??;
+                    self.channel.0.send(ServerMessage::ReminderCancelled { id })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListCommands => {
+                // they can't do this if they haven't initialized
+                if self.user_id.is_some() {
+                    let commands = self.state.commands.list()
+                        .into_iter()
+                        .map(|(name, description)| CommandInfo { name, description })
+                        .collect();
+                    self.channel.0.send(ServerMessage::Commands { commands })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::RegisterCommand { name, description } => {
+                if !self.state.features.bots {
+                    return Err(ServerError::FeatureDisabled("Bots"));
+                }
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    if !self.is_bot {
+                        return Err(ServerError::NotBot);
+                    }
+                    commands::validate_name(name)?;
+                    let description = description.trim().chars().take(MAX_STATUS_LENGTH).collect();
+                    if !self.state.commands.register(name, BotCommand { bot: user_id, description }) {
+                        return Err(CommandError::NameTaken.into());
+                    }
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ForwardMessage { id, recipient } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    if recipient == MessageRecipient::User(user_id) {
+                        return Err(ServerError::SelfMessage);
+                    }
+
+                    let state = self.state.clone();
+                    let message: MessageWithId = spawn_blocking(move || state.store.forward_message(id, user_id, recipient)).await??
+                        .into();
+                    self.state.queue_webhooks("MessageSent", message.clone());
+                    let server_message = ServerMessage::MessageSent { message, muted: false };
+                    self.send_to_recipient(server_message, recipient, user_id).await?;
                 } else {
                     warn!("Uninitialized user");
                 }
@@ -314,7 +1251,9 @@ impl WsHandler {
                 // they can't do this if they haven't initialized
                 if let Some(user_id) = self.user_id {
                     let state = self.state.clone();
-                    if let Some(message) = spawn_blocking(move || state.store.delete_message(id, user_id)).await?? {
+                    let as_admin = self.is_admin;
+                    if let Some(message) = spawn_blocking(move || state.store.delete_message(id, user_id, as_admin)).await?? {
+                        self.state.queue_webhooks("MessageDeleted", MessageWithId { id, message: message.clone() });
                         // notify all recipients that it was deleted
                         let server_message = ServerMessage::MessageDeleted { id };
                         self.send_to_recipient(server_message, message.recipient, message.sender).await?;
@@ -329,6 +1268,7 @@ impl WsHandler {
                     let state = self.state.clone();
                     let new_message = new_message.into();
                     if let Some(message) = spawn_blocking(move || state.store.edit_message(id, new_message, user_id)).await?? {
+                        self.state.queue_webhooks("MessageEdited", MessageWithId { id, message: message.clone() });
                         // notify all recipients that it was edited
                         let server_message = ServerMessage::MessageEdited { id, message: message.message };
                         self.send_to_recipient(server_message, message.recipient, message.sender).await?;
@@ -341,37 +1281,47 @@ impl WsHandler {
                 // they can't do this if they haven't initialized
                 if let Some(user_id) = self.user_id {
                     let state = self.state.clone();
-                    let new_tags = new_tags.into();
                     if let Some(message) = spawn_blocking(move || state.store.edit_message_tags(id, new_tags, user_id)).await?? {
                         // notify all recipients that it was edited
-                        let server_message = ServerMessage::MessageTagsEdited { id, tags: message.tags };
-                        self.send_to_recipient(server_message, message.recipient, message.sender).await?;
+                        self.state.tags_edited(id, message).await?;
                     }
                 } else {
                     warn!("Uninitialized user");
                 }
             },
-            ClientMessage::CreateGroup { name, members } => {
+            ClientMessage::CreateGroup { name, members, public } => {
                 // they can't do this if they haven't initialized
                 if let Some(user_id) = self.user_id {
                     let state = self.state.clone();
                     
                     let members: HashSet<_> = members.into_iter().collect();
                     let name = name.to_owned();
-                    let name_2 = name.clone();
-                    let (id, members) = spawn_blocking(move || {
+                    let group = spawn_blocking(move || {
+                        let (group_id, group, ..) = state.store.create_update_group(name, members, public, None, user_id)?;
                         // resolve the member usernames
                         let users = state.store.list_users()?;
-                        let member_names = members
-                            .iter()
-                            .filter_map(|id| users.get(&id).map(Into::into))
-                            .collect();
-                        let group_id = state.store.create_update_group(name_2, members, None, user_id)?;
-                        
-                        store::Result::Ok((group_id, member_names))
+                        store::Result::Ok(ServerGroup::new(group_id, group, &users))
                     }).await??;
+                    let id = group.id;
                     // broadcast this message to all members
-                    let server_message = ServerMessage::GroupAdded { group: ServerGroup { name, id, members } };
+                    let server_message = ServerMessage::GroupAdded { group };
+                    self.send_to_recipient(server_message, MessageRecipient::Group(id), user_id).await?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::SetGroupRole { id, user, role } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+
+                    let group = spawn_blocking(move || {
+                        let group = state.store.set_group_role(id, user, role, user_id)?;
+                        let users = state.store.list_users()?;
+                        store::Result::Ok(ServerGroup::new(id, group, &users))
+                    }).await??;
+                    // broadcast the new roles to all members
+                    let server_message = ServerMessage::GroupEdited { group };
                     self.send_to_recipient(server_message, MessageRecipient::Group(id), user_id).await?;
                 } else {
                     warn!("Uninitialized user");
@@ -382,7 +1332,8 @@ impl WsHandler {
                 if let Some(user_id) = self.user_id {
                     let state = self.state.clone();
                     
-                    let members = spawn_blocking(move || state.store.delete_group(id, user_id)).await??;
+                    let as_admin = self.is_admin;
+                    let members = spawn_blocking(move || state.store.delete_group(id, user_id, as_admin)).await??;
                     // broadcast this message to all members
                     let server_message = ServerMessage::GroupDeleted { id };
                     // send the message to each user in the group
@@ -403,65 +1354,673 @@ impl WsHandler {
                     
                     let members: HashSet<_> = new_members.into_iter().collect();
                     let name = new_name.to_owned();
-                    let name_2 = name.clone();
-                    let (added, removed, retained, members) = spawn_blocking(move || {
+                    let (added, removed, retained, group) = spawn_blocking(move || {
+                        // the difference in members comes from the same transaction as the edit
+                        let (_, group, added, removed) = state.store.create_update_group(name, members, false, Some(id), user_id)?;
+                        let retained: Vec<_> = group.members.keys()
+                            .filter(|member| !added.contains(member))
+                            .copied()
+                            .collect();
+
                         // resolve the member usernames
                         let users = state.store.list_users()?;
-                        let member_names = members
-                            .iter()
-                            .filter_map(|id| users.get(&id).map(Into::into))
+                        
+                        store::Result::Ok((added, removed, retained, ServerGroup::new(id, group, &users)))
+                    }).await??;
+
+                    self.send_group_changes(group, &added, &removed, &retained)?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::AddMembers { id, members } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+
+                    let members: HashSet<_> = members.into_iter().collect();
+                    let (added, retained, group) = spawn_blocking(move || {
+                        let (group, added) = state.store.add_group_members(id, members, user_id)?;
+                        let retained: Vec<_> = group.members.keys()
+                            .filter(|member| !added.contains(member))
+                            .copied()
                             .collect();
 
-                        // compute the difference in members
-                        let old_members = state.store.get_group_members(id)?
-                            .ok_or(StoreError::InvalidGroupId)?;
+                        // resolve the member usernames
+                        let users = state.store.list_users()?;
+                        store::Result::Ok((added, retained, ServerGroup::new(id, group, &users)))
+                    }).await??;
 
-                        let added: Vec<_> = members.difference(&old_members).copied().collect();
-                        let removed: Vec<_> = old_members.difference(&members).copied().collect();
-                        let retained: Vec<_> = members.intersection(&old_members).copied().collect();
-                        
-                        state.store.create_update_group(name_2, members, Some(id), user_id)?;
-                        
-                        store::Result::Ok((added, removed, retained, member_names))
+                    self.send_group_changes(group, &added, &[], &retained)?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::RemoveMembers { id, members } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+
+                    let members: HashSet<_> = members.into_iter().collect();
+                    let (removed, retained, group) = spawn_blocking(move || {
+                        let (group, removed) = state.store.remove_group_members(id, members, user_id)?;
+                        let retained: Vec<_> = group.members.keys().copied().collect();
+
+                        // resolve the member usernames
+                        let users = state.store.list_users()?;
+                        store::Result::Ok((removed, retained, ServerGroup::new(id, group, &users)))
                     }).await??;
 
-                    let group = ServerGroup { name, id, members };
-                    let added_message = ServerMessage::GroupAdded { group: group.clone() };
-                    let removed_message = ServerMessage::GroupDeleted { id };
-                    let edited_message = ServerMessage::GroupEdited { group };
-                    
-                    // broadcast the appropriate message to all members
-                    let users = self.state.users.read().unwrap();
+                    self.send_group_changes(group, &[], &removed, &retained)?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::LeaveGroup { id } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
 
-                    for member in added {
-                        if let Some(client) = users.get(&member) {
-                            client.send(added_message.clone())?;
+                    let remaining = spawn_blocking(move || {
+                        if let Some(group) = state.store.leave_group(id, user_id)? {
+                            let retained: Vec<_> = group.members.keys().copied().collect();
+
+                            // resolve the member usernames
+                            let users = state.store.list_users()?;
+                            store::Result::Ok(Some((retained, ServerGroup::new(id, group, &users))))
+                        } else {
+                            Ok(None)
                         }
+                    }).await??;
+
+                    if let Some((retained, group)) = remaining {
+                        self.send_group_changes(group, &[], &[user_id], &retained)?;
+                    } else {
+                        // they were the last member, so the group is gone
+                        self.channel.0.send(ServerMessage::GroupDeleted { id })?;
                     }
-                    for member in removed {
-                        if let Some(client) = users.get(&member) {
-                            client.send(removed_message.clone())?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::SetGroupVisibility { id, public } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+
+                    let group = spawn_blocking(move || {
+                        let group = state.store.set_group_visibility(id, public, user_id)?;
+                        let users = state.store.list_users()?;
+                        store::Result::Ok(ServerGroup::new(id, group, &users))
+                    }).await??;
+                    let server_message = ServerMessage::GroupEdited { group };
+                    self.send_to_recipient(server_message, MessageRecipient::Group(id), user_id).await?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListPublicChannels => {
+                // they can't do this if they haven't initialized
+                if self.user_id.is_some() {
+                    let state = self.state.clone();
+
+                    let channels = spawn_blocking(move || {
+                        let users = state.store.list_users()?;
+                        let channels = state.store.get_public_channels()?.into_iter()
+                            .map(|(id, group)| ServerGroup::new(id, group, &users))
+                            .collect();
+                        store::Result::Ok(channels)
+                    }).await??;
+                    self.send_message(&ServerMessage::PublicChannels { channels }).await;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::JoinChannel { id } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+
+                    let joined = spawn_blocking(move || {
+                        let (group, added) = state.store.join_public_channel(id, user_id)?;
+                        if !added {
+                            return store::Result::Ok(None);
                         }
+                        let retained: Vec<_> = group.members.keys()
+                            .filter(|member| **member != user_id)
+                            .copied()
+                            .collect();
+
+                        // resolve the member usernames
+                        let users = state.store.list_users()?;
+                        Ok(Some((retained, ServerGroup::new(id, group, &users))))
+                    }).await??;
+
+                    // nothing changes if they were already a member
+                    if let Some((retained, group)) = joined {
+                        self.send_group_changes(group, &[user_id], &[], &retained)?;
                     }
-                    for member in retained {
-                        if let Some(client) = users.get(&member) {
-                            client.send(edited_message.clone())?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::CreateInvite { group, expires_in, max_uses } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    if expires_in.is_some_and(|e| e <= 0) {
+                        return Err(ServerError::InvalidExpiry);
+                    }
+                    let expires = expires_in.map(|e| Utc::now().timestamp().saturating_add(e));
+
+                    let state = self.state.clone();
+                    let invite = spawn_blocking(move || state.store.create_group_invite(group, user_id, expires, max_uses)).await??
+                        .into();
+                    self.send_message(&ServerMessage::InviteCreated { invite }).await;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListInvites { group } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let invites = spawn_blocking(move || state.store.get_group_invites(group, user_id)).await??
+                        .into_iter()
+                        .map(Into::into)
+                        .collect();
+                    self.send_message(&ServerMessage::Invites { group, invites }).await;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::RevokeInvite { token } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let token = token.to_owned();
+                    let token_2 = token.clone();
+                    spawn_blocking(move || state.store.revoke_group_invite(&token_2, user_id)).await??;
+                    self.send_message(&ServerMessage::InviteRevoked { token }).await;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::RedeemInvite { token } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let token = token.to_owned();
+                    let joined = spawn_blocking(move || {
+                        let (id, group, added) = state.store.redeem_group_invite(&token, user_id)?;
+                        if !added {
+                            return store::Result::Ok(None);
                         }
+                        let retained: Vec<_> = group.members.keys()
+                            .filter(|member| **member != user_id)
+                            .copied()
+                            .collect();
+
+                        // resolve the member usernames
+                        let users = state.store.list_users()?;
+                        Ok(Some((retained, ServerGroup::new(id, group, &users))))
+                    }).await??;
+
+                    // nothing changes if they were already a member
+                    if let Some((retained, group)) = joined {
+                        self.send_group_changes(group, &[user_id], &[], &retained)?;
+                    }
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::RenameUser { id, new_name } => {
+                // only admins can do this
+                if let Some(admin) = self.user_id {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+                    validate_username(new_name)?;
+
+                    let state = self.state.clone();
+                    let name = new_name.to_owned();
+                    let name_2 = name.clone();
+                    let groups = spawn_blocking(move || {
+                        state.store.rename_user(id, name_2, admin)?;
+
+                        // their groups list members by name, so those change too
+                        let users = state.store.list_users()?;
+                        let groups: Vec<_> = state.store.get_groups_for_user(id)?.into_iter()
+                            .map(|(group_id, group)| ServerGroup::new(group_id, group, &users))
+                            .collect();
+                        store::Result::Ok(groups)
+                    }).await??;
+
+                    self.send_broadcast(ServerMessage::UserRenamed { id, name });
+                    for group in groups {
+                        let group_id = group.id;
+                        self.send_to_recipient(ServerMessage::GroupEdited { group }, MessageRecipient::Group(group_id), id).await?;
+                    }
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::SetUserDeactivated { id, deactivated } => {
+                // only admins can do this
+                if let Some(admin) = self.user_id {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+
+                    let state = self.state.clone();
+                    spawn_blocking(move || state.store.set_user_deactivated(id, deactivated, admin)).await??;
+                    if deactivated {
+                        self.state.disconnect(id, "Your account has been deactivated");
                     }
                 } else {
                     warn!("Uninitialized user");
                 }
+            },
+            ClientMessage::DeleteUser { id, erase_messages } => {
+                // only admins can do this
+                if let Some(admin) = self.user_id {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+
+                    let state = self.state.clone();
+                    let (groups, bots) = spawn_blocking(move || {
+                        // their bots are deactivated along with them
+                        let bots = state.store.get_bots(Some(id))?;
+                        let changed = state.store.delete_user(id, erase_messages, admin)?;
+
+                        // resolve the member usernames of the groups they were removed from
+                        let users = state.store.list_users()?;
+                        let groups: Vec<_> = changed.into_iter()
+                            .filter_map(|(group_id, group)| {
+                                let group = group?;
+                                let members: Vec<_> = group.members.keys().copied().collect();
+                                Some((members, ServerGroup::new(group_id, group, &users)))
+                            })
+                            .collect();
+                        store::Result::Ok((groups, bots))
+                    }).await??;
+
+                    self.state.disconnect(id, "Your account has been deleted");
+                    for (bot_id, _) in bots {
+                        self.state.disconnect(bot_id, "The bot's owner has been deleted");
+                    }
+                    self.send_broadcast(ServerMessage::UserDeleted { id });
+                    for (members, group) in groups {
+                        self.send_group_changes(group, &[], &[], &members)?;
+                    }
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::SetUserAdmin { id, admin } => {
+                // only admins can do this
+                if let Some(by) = self.user_id {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+
+                    // takes effect the next time they log in
+                    let state = self.state.clone();
+                    spawn_blocking(move || state.store.set_user_admin(id, admin, by)).await??;
+                    // a live session would keep its rights until then, so end it now
+                    if !admin {
+                        self.state.disconnect(id, "Your admin rights have been revoked");
+                    }
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListAllGroups => {
+                // only admins can do this
+                if self.user_id.is_some() {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+
+                    let state = self.state.clone();
+                    let groups = spawn_blocking(move || {
+                        let users = state.store.list_users()?;
+                        let groups = state.store.get_all_groups()?.into_iter()
+                            .map(|(id, group)| ServerGroup::new(id, group, &users))
+                            .collect();
+                        store::Result::Ok(groups)
+                    }).await??;
+
+                    self.audit_admin("listed all groups".into()).await?;
+                    self.send_message(&ServerMessage::AllGroups { groups }).await;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::GetStats => {
+                // only admins can do this
+                if self.user_id.is_some() {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+
+                    let state = self.state.clone();
+                    let stats = spawn_blocking(move || state.store.get_stats()).await??;
+                    let sessions = self.state.users.read().unwrap().len();
+
+                    self.audit_admin("inspected store statistics".into()).await?;
+                    self.send_message(&ServerMessage::Stats { stats, sessions }).await;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::DisconnectUser { id } => {
+                // only admins can do this
+                if self.user_id.is_some() {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+
+                    self.audit_admin(format!("disconnected user {id}")).await?;
+                    self.state.disconnect(id, "You were disconnected by an admin");
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::RegisterPush { endpoint, p256dh, auth } => {
+                if !self.state.features.push {
+                    return Err(ServerError::FeatureDisabled("Push notifications"));
+                }
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    // push services are always public https servers
+                    let valid_endpoint = targets::is_allowed(endpoint, false);
+                    let p256dh_len = URL_SAFE_NO_PAD.decode(p256dh).map(|k| k.len());
+                    let auth_len = URL_SAFE_NO_PAD.decode(auth).map(|k| k.len());
+                    // an uncompressed P-256 point and a 16 byte secret
+                    if !valid_endpoint || p256dh_len != Ok(65) || auth_len != Ok(16) {
+                        return Err(ServerError::InvalidPushSubscription);
+                    }
+
+                    let subscription = PushSubscription { endpoint: endpoint.into(), p256dh: p256dh.into(), auth: auth.into() };
+                    let state = self.state.clone();
+                    spawn_blocking(move || state.store.add_push_subscription(user_id, &subscription)).await??;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::UnregisterPush { endpoint } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let endpoint = endpoint.to_owned();
+                    spawn_blocking(move || state.store.remove_push_subscription(user_id, &endpoint)).await??;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::CreateWebhook { url, trigger } => {
+                if !self.state.features.webhooks {
+                    return Err(ServerError::FeatureDisabled("Webhooks"));
+                }
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    if !targets::is_allowed(url, self.state.allow_private_webhooks) {
+                        return Err(ServerError::InvalidWebhookUrl);
+                    }
+
+                    let state = self.state.clone();
+                    let as_admin = self.is_admin;
+                    let url = url.to_owned();
+                    let (id, webhook) = spawn_blocking(move || state.store.create_webhook(user_id, url, trigger, as_admin)).await??;
+                    let webhook = WebhookWithId { secret: Some(webhook.secret.clone()), ..(id, webhook).into() };
+                    self.channel.0.send(ServerMessage::WebhookCreated { webhook })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListWebhooks => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    // admins see everyone's webhooks
+                    let owner = (!self.is_admin).then_some(user_id);
+                    let webhooks = spawn_blocking(move || state.store.get_webhooks(owner)).await??
+                        .into_iter()
+                        .map(WebhookWithId::from)
+                        .collect();
+                    if self.is_admin {
+                        self.audit_admin("listed all webhooks".into()).await?;
+                    }
+                    self.channel.0.send(ServerMessage::Webhooks { webhooks })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::DeleteWebhook { id } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let as_admin = self.is_admin;
+                    spawn_blocking(move || state.store.delete_webhook(id, user_id, as_admin)).await??;
+                    self.channel.0.send(ServerMessage::WebhookDeleted { id })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::CreateIncomingWebhook { recipient, bot_name } => {
+                if !self.state.features.webhooks {
+                    return Err(ServerError::FeatureDisabled("Webhooks"));
+                }
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    validate_username(bot_name)?;
+                    if bot_name.is_empty() {
+                        return Err(ServerError::InvalidUsername);
+                    }
+
+                    let state = self.state.clone();
+                    let bot_name = bot_name.to_owned();
+                    let (webhook, bot, group) = spawn_blocking(move || {
+                        let (token, webhook, group) = state.store.create_incoming_webhook(user_id, recipient, bot_name.clone())?;
+                        let details = UserDetails { bot: true, ..Default::default() };
+                        let bot = ServerUser::new(webhook.bot, bot_name, Some(details), false);
+
+                        // the bot joined the group
+                        let users = state.store.list_users()?;
+                        let group = match (recipient, group) {
+                            (MessageRecipient::Group(group_id), Some(group)) => {
+                                let members: Vec<_> = group.members.keys().copied().collect();
+                                Some((members, ServerGroup::new(group_id, group, &users)))
+                            },
+                            _ => None
+                        };
+                        store::Result::Ok((IncomingWebhookWithToken { token: Some(token), webhook }, bot, group))
+                    }).await??;
+
+                    self.send_broadcast(ServerMessage::UserAdded { user: bot });
+                    if let Some((members, group)) = group {
+                        self.send_group_changes(group, &[], &[], &members)?;
+                    }
+                    self.channel.0.send(ServerMessage::IncomingWebhookCreated { webhook })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListIncomingWebhooks => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    // admins see everyone's webhooks
+                    let creator = (!self.is_admin).then_some(user_id);
+                    let webhooks = spawn_blocking(move || state.store.get_incoming_webhooks(creator)).await??
+                        .into_iter()
+                        .map(IncomingWebhookWithToken::from)
+                        .collect();
+                    if self.is_admin {
+                        self.audit_admin("listed all incoming webhooks".into()).await?;
+                    }
+                    self.channel.0.send(ServerMessage::IncomingWebhooks { webhooks })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::DeleteIncomingWebhook { bot } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let as_admin = self.is_admin;
+                    spawn_blocking(move || state.store.delete_incoming_webhook(bot, user_id, as_admin)).await??;
+                    self.channel.0.send(ServerMessage::IncomingWebhookDeleted { bot })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::CreateBot { name } => {
+                if !self.state.features.bots {
+                    return Err(ServerError::FeatureDisabled("Bots"));
+                }
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    validate_username(name)?;
+                    if name.is_empty() {
+                        return Err(ServerError::InvalidUsername);
+                    }
+
+                    let state = self.state.clone();
+                    let name = name.to_owned();
+                    let (bot, user) = spawn_blocking(move || {
+                        let (id, bot) = state.store.create_bot(user_id, name.clone())?;
+                        let details = UserDetails { bot: true, ..Default::default() };
+                        let bot = BotWithId { token: Some(bot.token.clone()), ..(id, bot).into() };
+                        store::Result::Ok((bot, ServerUser::new(id, name, Some(details), false)))
+                    }).await??;
+
+                    self.send_broadcast(ServerMessage::UserAdded { user });
+                    self.channel.0.send(ServerMessage::BotCreated { bot })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ListBots => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    // admins see everyone's bots
+                    let owner = (!self.is_admin).then_some(user_id);
+                    let bots = spawn_blocking(move || state.store.get_bots(owner)).await??
+                        .into_iter()
+                        .map(BotWithId::from)
+                        .collect();
+                    if self.is_admin {
+                        self.audit_admin("listed all bots".into()).await?;
+                    }
+                    self.channel.0.send(ServerMessage::Bots { bots })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::ResetBotToken { id } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let as_admin = self.is_admin;
+                    let bot = spawn_blocking(move || state.store.reset_bot_token(id, user_id, as_admin)).await??;
+
+                    // anything using the old token has to log in again
+                    self.state.disconnect(id, "The bot's token has been reset");
+                    let bot = BotWithId { token: Some(bot.token.clone()), ..(id, bot).into() };
+                    self.channel.0.send(ServerMessage::BotTokenReset { bot })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::DeleteBot { id } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    let as_admin = self.is_admin;
+                    let groups = spawn_blocking(move || {
+                        let changed = state.store.delete_bot(id, user_id, as_admin)?;
+
+                        // resolve the member usernames of the groups it was removed from
+                        let users = state.store.list_users()?;
+                        let groups: Vec<_> = changed.into_iter()
+                            .filter_map(|(group_id, group)| {
+                                let group = group?;
+                                let members: Vec<_> = group.members.keys().copied().collect();
+                                Some((members, ServerGroup::new(group_id, group, &users)))
+                            })
+                            .collect();
+                        store::Result::Ok(groups)
+                    }).await??;
+
+                    self.state.disconnect(id, "This bot has been deleted");
+                    self.send_broadcast(ServerMessage::UserDeleted { id });
+                    for (members, group) in groups {
+                        self.send_group_changes(group, &[], &[], &members)?;
+                    }
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::SetUserBlocked { id, blocked } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    spawn_blocking(move || state.store.set_user_blocked(user_id, id, blocked)).await??;
+                    self.channel.0.send(ServerMessage::UserBlocked { id, blocked })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::SetConversationMuted { conversation, muted } => {
+                // they can't do this if they haven't initialized
+                if let Some(user_id) = self.user_id {
+                    let state = self.state.clone();
+                    spawn_blocking(move || state.store.set_conversation_muted(user_id, conversation, muted)).await??;
+                    self.channel.0.send(ServerMessage::ConversationMuted { conversation, muted })?;
+                } else {
+                    warn!("Uninitialized user");
+                }
+            },
+            ClientMessage::GetAuditLog { from, to } => {
+                // only admins can do this
+                if self.user_id.is_some() {
+                    if !self.is_admin {
+                        return Err(ServerError::NotAdmin);
+                    }
+
+                    let state = self.state.clone();
+                    let entries = spawn_blocking(move || state.store.get_audit_log(from, to)).await??;
+                    self.send_message(&ServerMessage::AuditLog { entries }).await;
+                } else {
+                    warn!("Uninitialized user");
+                }
             }
         }
         Ok(())
     }
 
     pub async fn handle(&mut self) {
+        let shutdown = self.state.shutdown.clone();
         loop {
             select! {
+                _ = shutdown.cancelled() => {
+                    // let the client know it can come back soon
+                    self.send_message(&ServerMessage::ServerShutdown { reconnect_after: RECONNECT_AFTER }).await;
+                    let close = CloseFrame { code: close_code::AWAY, reason: "Server is shutting down".into() };
+                    if let Err(err) = self.socket.send(ws::Message::Close(Some(close))).await {
+                        warn!("Could not close websocket: {err}");
+                    }
+                    break;
+                },
                 message = self.channel.1.recv() => {
                     // somebody wants us to send a message to this client
-                    if let Some(message) = message { self.send_message(&message).await } else { break; }
+                    if let Some(message) = message {
+                        self.send_message(&message).await;
+                        if matches!(message, ServerMessage::Disconnected { .. }) { break; }
+                    } else { break; }
                 },
                 message = self.socket.next() => {
                     // message from the client
@@ -469,6 +2028,10 @@ impl WsHandler {
                         None => break,
                         Some(Ok(message)) => {
                             match message {
+                                ws::Message::Binary(_) if !self.rate_limiter.allow(Instant::now()) => {
+                                    let message = ServerMessage::Error { err: ServerError::RateLimited.to_string() };
+                                    self.send_message(&message).await;
+                                },
                                 ws::Message::Binary(data) => {
                                     // decode it
                                     match rmp_serde::from_slice(&data) {
@@ -512,9 +2075,61 @@ impl Drop for WsHandler {
                 let mut users = self.state.users.write().unwrap();
                 users.remove(&id);
             }
+            if self.is_bot {
+                self.state.commands.unregister_owner(id);
+            }
 
             let message = ServerMessage::UserOffline { id };
             self.send_broadcast(message);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::{Duration, Instant}};
+
+    use clap::Parser;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{RateLimiter, WsState};
+    use crate::{config::{Args, Config, RateLimit}, store::{MessageRecipient, WebhookTrigger}};
+
+    #[test]
+    fn rate_limiter() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(RateLimit { per_second: 2, burst: 3 });
+        assert!((0..3).all(|_| limiter.allow(start)));
+        assert!(!limiter.allow(start));
+        // half a second refills one token
+        assert!(limiter.allow(start + Duration::from_millis(500)));
+        assert!(!limiter.allow(start + Duration::from_millis(500)));
+        // never more than the burst
+        let later = start + Duration::from_secs(60);
+        assert_eq!((0..10).filter(|_| limiter.allow(later)).count(), 3);
+
+        let mut unlimited = RateLimiter::new(RateLimit { per_second: 0, burst: 0 });
+        assert!((0..1000).all(|_| unlimited.allow(start)));
+    }
+
+    #[tokio::test]
+    async fn tag_edits_reach_webhooks() {
+        let config = Config::load(&Args::try_parse_from(["send-to-computer"]).unwrap()).unwrap();
+        let state = Arc::new(WsState::new(&config, CancellationToken::new()).unwrap());
+        let sender = state.store.create_user("a".into()).unwrap();
+        let recipient = state.store.create_user("b".into()).unwrap();
+        let trigger = WebhookTrigger::Tag("todo".into());
+        let (webhook, _) = state.store.create_webhook(recipient, "https://example.com/hook".into(), trigger, false).unwrap();
+
+        let (id, _) = state.store.send_message("a".into(), sender, MessageRecipient::User(recipient)).unwrap();
+        let message = state.store.edit_message_tags(id, vec!["todo".into()], sender).unwrap().unwrap();
+        state.tags_edited(id, message).await.unwrap();
+
+        // deliveries are queued in the background
+        state.tasks.close();
+        state.tasks.wait().await;
+        let due = state.store.get_due_webhook_deliveries(i64::MAX).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!((due[0].1.webhook, &*due[0].1.event), (webhook, "MessageEdited"));
+    }
+}
@@ -1,4 +1,4 @@
-const cacheName = "stc-cache-v4";
+const cacheName = "stc-cache-v5";
 const urlsToCache = [
   "/",
   "/index.html",
@@ -34,3 +34,25 @@ self.addEventListener('activate', event =>
     }
   })
 );
+
+self.addEventListener("push", event => {
+  const notification = event.data?.json();
+  if (!notification) return;
+  event.waitUntil(
+    self.registration.showNotification(notification.title, {
+      body: notification.body,
+      icon: "/icons/icon-192.png",
+      // newer messages in the same conversation replace older ones
+      tag: JSON.stringify(notification.recipient),
+      data: notification
+    })
+  );
+});
+
+self.addEventListener("notificationclick", event => {
+  event.notification.close();
+  event.waitUntil(
+    clients.matchAll({ type: "window" })
+      .then(windows => windows.length > 0 ? windows[0].focus() : clients.openWindow("/"))
+  );
+});